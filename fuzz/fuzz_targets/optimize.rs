@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use oxipng::{Options, internal_tests::*, optimize_from_memory};
+
+// Keep allocations for the decoded image within the fuzzer's memory limit
+const MAX_SIZE: usize = 1 << 24;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, data)) = data.split_first() else {
+        return;
+    };
+    // Only the fastest presets are used, so that each run stays quick
+    let mut opts = Options::from_preset(selector % 2);
+    opts.fix_errors = selector & 0x02 != 0;
+    opts.optimize_alpha = selector & 0x04 != 0;
+    opts.interlace = match selector >> 3 & 0x03 {
+        0 => None,
+        1 => Some(false),
+        _ => Some(true),
+    };
+    opts.max_decompressed_size = Some(MAX_SIZE);
+
+    let Ok(original) = PngData::from_slice(data, &opts) else {
+        return;
+    };
+    let Ok(output) = optimize_from_memory(data, &opts) else {
+        return;
+    };
+    verify_output(&output, &original, opts.merge_frames).expect("Output does not match the input");
+});
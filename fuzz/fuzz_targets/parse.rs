@@ -0,0 +1,68 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use oxipng::{Options, RecoveryMode, internal_tests::*};
+
+// Keep allocations for the decoded image within the fuzzer's memory limit
+const MAX_SIZE: usize = 1 << 24;
+
+fuzz_target!(|data: &[u8]| {
+    parse_chunks(data);
+
+    for (fix_errors, recover) in [
+        (false, None),
+        (true, Some(RecoveryMode::Pad)),
+        (true, Some(RecoveryMode::Crop)),
+    ] {
+        let opts = Options {
+            fix_errors,
+            recover,
+            max_decompressed_size: Some(MAX_SIZE),
+            ..Options::default()
+        };
+        let Ok(png) = PngData::from_slice(data, &opts) else {
+            continue;
+        };
+
+        // Exercise the deinterlace and pixel conversion paths on everything that parsed
+        let _ = png.raw.rgba16_pixels();
+        for frame in &png.frames {
+            if let Ok(image) = frame.image(&png.raw.ihdr) {
+                let _ = image.rgba16_pixels();
+            }
+        }
+        for chunk in png.aux_chunks.iter().filter(|c| &c.name == b"iCCP") {
+            let _ = extract_icc(chunk, Some(MAX_SIZE));
+        }
+
+        // Anything we can read must be written out in a form we can read back
+        let output = png.output();
+        PngData::from_slice(&output, &Options::default()).expect("Failed to read back output");
+    }
+});
+
+/// Walk the chunks directly, parsing any headers found along the way
+fn parse_chunks(data: &[u8]) {
+    if data.len() < 8 || !file_header_is_valid(&data[..8]) {
+        return;
+    }
+    let mut byte_offset = 8;
+    while let Ok(Some(chunk)) = parse_next_chunk(data, &mut byte_offset, true) {
+        match &chunk.name {
+            b"IHDR" => {
+                let _ = parse_ihdr_chunk(chunk.data, None, None);
+            }
+            b"fcTL" => {
+                let _ = Frame::from_fctl_data(chunk.data);
+            }
+            b"iCCP" => {
+                let iccp = Chunk {
+                    name: chunk.name,
+                    data: chunk.data.to_vec(),
+                };
+                let _ = extract_icc(&iccp, Some(MAX_SIZE));
+            }
+            _ => (),
+        }
+    }
+}
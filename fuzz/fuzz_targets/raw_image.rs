@@ -0,0 +1,80 @@
+#![no_main]
+
+use std::sync::Arc;
+
+use libfuzzer_sys::fuzz_target;
+use oxipng::{
+    BitDepth, ColorType, Options, RGB16, RGBA8, RawImage,
+    internal_tests::{IhdrData, PngData, PngImage, verify_output},
+};
+
+fuzz_target!(|data: &[u8]| {
+    // The first bytes describe the image and the remainder is the pixel data
+    let Some((header, mut data)) = data.split_first_chunk::<6>() else {
+        return;
+    };
+    let width = u32::from(header[0] % 64) + 1;
+    let height = u32::from(header[1] % 64) + 1;
+    let bit_depth = match header[2] % 5 {
+        0 => BitDepth::One,
+        1 => BitDepth::Two,
+        2 => BitDepth::Four,
+        3 => BitDepth::Eight,
+        _ => BitDepth::Sixteen,
+    };
+    let has_trns = header[4] & 0x01 != 0;
+    let color_type = match header[3] % 5 {
+        0 => ColorType::Grayscale {
+            transparent_shade: has_trns.then_some(u16::from(header[5])),
+        },
+        1 => ColorType::RGB {
+            transparent_color: has_trns.then_some(RGB16::new(0, u16::from(header[5]), 0)),
+        },
+        2 => {
+            let len = usize::from(header[5]).min(data.len() / 4);
+            let (palette, rest) = data.split_at(len * 4);
+            data = rest;
+            let mut palette: Vec<_> = palette
+                .chunks_exact(4)
+                .map(|c| RGBA8::new(c[0], c[1], c[2], c[3]))
+                .collect();
+            // Out of range indices are not valid, so fill the rest of the palette
+            palette.resize(256, RGBA8::new(0, 0, 0, 255));
+            ColorType::Indexed { palette }
+        }
+        3 => ColorType::GrayscaleAlpha,
+        _ => ColorType::RGBA,
+    };
+
+    let ihdr = IhdrData {
+        width,
+        height,
+        color_type: color_type.clone(),
+        bit_depth,
+        interlaced: false,
+    };
+    let Some(data) = data.get(..ihdr.raw_data_size() - height as usize) else {
+        return;
+    };
+    let Ok(raw) = RawImage::new(width, height, color_type, bit_depth, data.to_vec()) else {
+        return;
+    };
+
+    let mut opts = Options::from_preset(header[4] >> 1 & 0x01);
+    opts.optimize_alpha = header[4] & 0x04 != 0;
+    let output = raw
+        .create_optimized_png(&opts)
+        .expect("Failed to optimize raw image");
+
+    let original = PngData {
+        raw: Arc::new(PngImage {
+            ihdr,
+            data: data.to_vec(),
+        }),
+        idat_data: Vec::new(),
+        aux_chunks: Vec::new(),
+        frames: Vec::new(),
+        recovered: false,
+    };
+    verify_output(&output, &original, false).expect("Output does not match the input");
+});
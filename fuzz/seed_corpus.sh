@@ -0,0 +1,12 @@
+#!/bin/bash
+# Seed the fuzzing corpora from the test files
+set -e
+cd "$(dirname "$0")"
+
+mkdir -p corpus/parse corpus/optimize
+for file in ../tests/files/*.png; do
+    name=$(basename "$file")
+    cp "$file" "corpus/parse/$name"
+    # The optimize target reads a byte of option flags before the PNG data
+    { printf '\0'; cat "$file"; } > "corpus/optimize/$name"
+done
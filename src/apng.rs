@@ -1,9 +1,30 @@
+use std::ops::Range;
+
+use log::warn;
+use rgb::RGBA8;
+
 use crate::{
-    PngResult,
+    Deadline, PngResult,
+    colors::{BitDepth, ColorType},
+    deflate::Deflater,
     error::PngError,
-    headers::{read_be_u16, read_be_u32},
+    headers::{IhdrData, read_be_u16, read_be_u32},
+    png::{PngData, PngImage},
 };
 
+/// Leave the canvas as it is after the frame is displayed
+pub const DISPOSE_OP_NONE: u8 = 0;
+/// Clear the frame region to transparent black after the frame is displayed
+pub const DISPOSE_OP_BACKGROUND: u8 = 1;
+/// Revert the frame region to its previous contents after the frame is displayed
+pub const DISPOSE_OP_PREVIOUS: u8 = 2;
+/// Replace the frame region with the frame pixels
+pub const BLEND_OP_SOURCE: u8 = 0;
+/// Alpha-composite the frame pixels over the frame region
+pub const BLEND_OP_OVER: u8 = 1;
+
+const TRANSPARENT: RGBA8 = RGBA8::new(0, 0, 0, 0);
+
 #[derive(Debug, Clone)]
 /// Animated PNG frame
 pub struct Frame {
@@ -46,6 +67,41 @@ impl Frame {
         })
     }
 
+    /// Decode the frame data using the color type and bit depth of the given image header
+    pub fn image(&self, ihdr: &IhdrData) -> PngResult<PngImage> {
+        PngImage::new(
+            IhdrData {
+                width: self.width,
+                height: self.height,
+                ..ihdr.clone()
+            },
+            &self.data,
+        )
+    }
+
+    /// Whether the dispose and blend operations are known values
+    const fn has_valid_ops(&self) -> bool {
+        self.dispose_op <= DISPOSE_OP_PREVIOUS && self.blend_op <= BLEND_OP_OVER
+    }
+
+    /// Whether the frame region lies entirely within a canvas of the given size
+    fn fits(&self, width: u32, height: u32) -> bool {
+        self.width > 0
+            && self.height > 0
+            && u64::from(self.x_offset) + u64::from(self.width) <= u64::from(width)
+            && u64::from(self.y_offset) + u64::from(self.height) <= u64::from(height)
+    }
+
+    /// The ranges of canvas indices covered by each row of the frame region
+    fn rows(&self, canvas_width: usize) -> impl Iterator<Item = Range<usize>> + use<> {
+        let x = self.x_offset as usize;
+        let width = self.width as usize;
+        (self.y_offset as usize..(self.y_offset + self.height) as usize).map(move |y| {
+            let start = y * canvas_width + x;
+            start..start + width
+        })
+    }
+
     /// Construct the data for a fcTL chunk using the given sequence number
     #[must_use]
     pub fn fctl_data(&self, sequence_number: u32) -> Vec<u8> {
@@ -70,3 +126,528 @@ impl Frame {
         byte_data
     }
 }
+
+/// Blend a pixel over another, if the result is exact regardless of how it is calculated
+const fn blend_over(dst: RGBA8, src: RGBA8) -> Option<RGBA8> {
+    match (src.a, dst.a) {
+        (255, _) | (_, 0) => Some(src),
+        (0, _) => Some(dst),
+        _ => None,
+    }
+}
+
+/// Blend a pixel over another, using the calculation from the APNG specification
+fn blended(dst: RGBA8, src: RGBA8) -> RGBA8 {
+    blend_over(dst, src).unwrap_or_else(|| {
+        let u = u32::from(src.a) * 255;
+        let v = (255 - u32::from(src.a)) * u32::from(dst.a);
+        let al = u + v;
+        let mix = |s: u8, d: u8| ((u32::from(s) * u + u32::from(d) * v) / al) as u8;
+        RGBA8::new(
+            mix(src.r, dst.r),
+            mix(src.g, dst.g),
+            mix(src.b, dst.b),
+            (al / 255) as u8,
+        )
+    })
+}
+
+/// Composite each frame of an animation onto a canvas of the given size, returning the full canvas
+/// as it is displayed for each frame.
+///
+/// Returns None if a frame is invalid. If `exact` is set, also returns None if partially
+/// transparent pixels are blended over a non-transparent canvas, as decoders may differ in the
+/// exact result of this.
+pub fn composite_frames(
+    width: u32,
+    height: u32,
+    frames: &[Frame],
+    pixels: &[Vec<RGBA8>],
+    exact: bool,
+) -> Option<Vec<Vec<RGBA8>>> {
+    let canvas_width = width as usize;
+    let mut canvas = vec![TRANSPARENT; canvas_width * height as usize];
+    let mut outputs = Vec::with_capacity(frames.len());
+    for (frame, pixels) in frames.iter().zip(pixels) {
+        if !frame.fits(width, height)
+            || frame.dispose_op > DISPOSE_OP_PREVIOUS
+            || frame.blend_op > BLEND_OP_OVER
+            || pixels.len() != frame.width as usize * frame.height as usize
+        {
+            return None;
+        }
+        // Disposing the first frame to previous is the same as to background, as the canvas starts
+        // out transparent
+        let previous = (frame.dispose_op == DISPOSE_OP_PREVIOUS).then(|| canvas.clone());
+        for (row, src) in frame
+            .rows(canvas_width)
+            .zip(pixels.chunks_exact(frame.width as usize))
+        {
+            for (dst, &src) in canvas[row].iter_mut().zip(src) {
+                *dst = if frame.blend_op == BLEND_OP_SOURCE {
+                    src
+                } else if exact {
+                    blend_over(*dst, src)?
+                } else {
+                    blended(*dst, src)
+                };
+            }
+        }
+        outputs.push(canvas.clone());
+        if let Some(previous) = previous {
+            canvas = previous;
+        } else if frame.dispose_op == DISPOSE_OP_BACKGROUND {
+            for row in frame.rows(canvas_width) {
+                canvas[row].fill(TRANSPARENT);
+            }
+        }
+    }
+    Some(outputs)
+}
+
+/// Find the smallest frame that will turn `canvas` into `output`, using either blend operation
+fn smallest_frame(
+    canvas: &[RGBA8],
+    output: &[RGBA8],
+    template: &Frame,
+    canvas_width: usize,
+) -> (Frame, Vec<RGBA8>, usize) {
+    // Find the bounding box of the changed pixels
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for (i, (a, b)) in canvas.iter().zip(output).enumerate() {
+        if a != b {
+            let (x, y) = (i % canvas_width, i / canvas_width);
+            bounds = Some(bounds.map_or((x, y, x, y), |(x0, y0, x1, y1)| {
+                (x0.min(x), y0.min(y), x1.max(x), y1.max(y))
+            }));
+        }
+    }
+    // If nothing changed, a frame must still have at least one pixel
+    let (x0, y0, x1, y1) = bounds.unwrap_or_default();
+    let region = Frame {
+        width: (x1 - x0 + 1) as u32,
+        height: (y1 - y0 + 1) as u32,
+        x_offset: x0 as u32,
+        y_offset: y0 as u32,
+        data: Vec::new(),
+        ..template.clone()
+    };
+    let source: Vec<RGBA8> = region
+        .rows(canvas_width)
+        .flat_map(|row| output[row].iter().copied())
+        .collect();
+    // Unchanged pixels can be made transparent when blending over the canvas, as long as the
+    // changed pixels are exact when blended
+    let over: Option<Vec<RGBA8>> = region
+        .rows(canvas_width)
+        .flat_map(|row| canvas[row.clone()].iter().zip(&output[row]))
+        .map(|(&dst, &src)| {
+            if dst == src {
+                Some(TRANSPARENT)
+            } else {
+                blend_over(dst, src).filter(|&px| px == src).map(|_| src)
+            }
+        })
+        .collect();
+
+    let source_size = estimated_size(&source);
+    let source = (
+        Frame {
+            blend_op: BLEND_OP_SOURCE,
+            ..region.clone()
+        },
+        source,
+        source_size,
+    );
+    match over {
+        Some(over) if estimated_size(&over) < source_size => {
+            let size = estimated_size(&over);
+            (
+                Frame {
+                    blend_op: BLEND_OP_OVER,
+                    ..region
+                },
+                over,
+                size,
+            )
+        }
+        _ => source,
+    }
+}
+
+/// Quickly estimate the compressed size of the pixels
+fn estimated_size(pixels: &[RGBA8]) -> usize {
+    let bytes: Vec<u8> = pixels
+        .iter()
+        .flat_map(|px| [px.r, px.g, px.b, px.a])
+        .collect();
+    Deflater::Libdeflater { compression: 1 }
+        .deflate(&bytes, None)
+        .map_or(usize::MAX, |data| data.len())
+}
+
+/// A possible encoding of a frame, along with the dispose operation of the previous frame
+struct FrameCandidate {
+    dispose_op: u8,
+    /// The canvas before this frame is drawn
+    canvas: Vec<RGBA8>,
+    frame: Frame,
+    pixels: Vec<RGBA8>,
+    size: usize,
+}
+
+/// Optimize the frames of an animation by cropping each frame to the region that changed, and
+/// selecting the dispose and blend operations that give the smallest result. The first frame is
+/// left unchanged, and the composited output of every frame is guaranteed to be identical.
+///
+/// Returns the new frames and their pixels, or None if the animation could not be composited.
+pub fn optimized_frames(
+    width: u32,
+    height: u32,
+    frames: &[Frame],
+    pixels: &[Vec<RGBA8>],
+    deadline: &Deadline,
+) -> Option<(Vec<Frame>, Vec<Vec<RGBA8>>)> {
+    let outputs = composite_frames(width, height, frames, pixels, true)?;
+    let canvas_width = width as usize;
+    let mut new_frames = frames.to_vec();
+    let mut new_pixels = pixels.get(..1)?.to_vec();
+    // The canvas before the previous frame was drawn
+    let mut previous = vec![TRANSPARENT; outputs[0].len()];
+    for i in 1..frames.len() {
+        if deadline.passed() {
+            return None;
+        }
+        // The dispose operation of the first frame is kept, as it may be the default image
+        let prev = new_frames[i - 1].clone();
+        let dispose_ops: &[u8] = if i == 1 {
+            &[prev.dispose_op]
+        } else {
+            &[DISPOSE_OP_NONE, DISPOSE_OP_BACKGROUND, DISPOSE_OP_PREVIOUS]
+        };
+        let mut best: Option<FrameCandidate> = None;
+        for &dispose_op in dispose_ops {
+            let mut canvas = if dispose_op == DISPOSE_OP_PREVIOUS {
+                previous.clone()
+            } else {
+                outputs[i - 1].clone()
+            };
+            if dispose_op == DISPOSE_OP_BACKGROUND {
+                for row in prev.rows(canvas_width) {
+                    canvas[row].fill(TRANSPARENT);
+                }
+            }
+            let (frame, pixels, size) =
+                smallest_frame(&canvas, &outputs[i], &frames[i], canvas_width);
+            if best.as_ref().is_none_or(|b| size < b.size) {
+                best = Some(FrameCandidate {
+                    dispose_op,
+                    canvas,
+                    frame,
+                    pixels,
+                    size,
+                });
+            }
+        }
+        let best = best?;
+        new_frames[i - 1].dispose_op = best.dispose_op;
+        new_frames[i] = best.frame;
+        new_pixels.push(best.pixels);
+        previous = best.canvas;
+    }
+    // Verify the result
+    let new_outputs = composite_frames(width, height, &new_frames, &new_pixels, true)?;
+    (new_outputs == outputs).then_some((new_frames, new_pixels))
+}
+
+/// Construct an 8-bit RGBA image from pixels
+fn rgba_image(width: u32, height: u32, pixels: &[RGBA8]) -> PngImage {
+    PngImage {
+        ihdr: IhdrData {
+            width,
+            height,
+            color_type: ColorType::RGBA,
+            bit_depth: BitDepth::Eight,
+            interlaced: false,
+        },
+        data: pixels
+            .iter()
+            .flat_map(|px| [px.r, px.g, px.b, px.a])
+            .collect(),
+    }
+}
+
+/// Get the frames of an APNG, including the default image if it is part of the animation
+///
+/// Returns the frames and whether the first one is the default image
+fn animation_frames(png: &PngData) -> PngResult<(Vec<Frame>, bool)> {
+    // The default image is part of the animation if it has its own fcTL chunk
+    let default_frame = png
+        .aux_chunks
+        .iter()
+        .find(|c| &c.name == b"fcTL")
+        .map(|c| Frame::from_fctl_data(&c.data))
+        .transpose()?;
+    let is_default = default_frame.is_some();
+    let frames = default_frame
+        .into_iter()
+        .chain(png.frames.iter().cloned())
+        .collect();
+    Ok((frames, is_default))
+}
+
+/// Composite every frame of an APNG, returning the full canvas as it is displayed for each frame.
+/// A static PNG is treated as having a single frame.
+pub fn composited_apng(png: &PngData) -> PngResult<Vec<Vec<RGBA8>>> {
+    let ihdr = &png.raw.ihdr;
+    let unsupported = || PngError::new("Compositing 16-bit frames is not supported");
+    let default_pixels = png.raw.rgba_pixels().ok_or_else(unsupported)?;
+    let (frames, is_default) = animation_frames(png)?;
+    if frames.is_empty() {
+        return Ok(vec![default_pixels]);
+    }
+    let mut pixels = Vec::with_capacity(frames.len());
+    if is_default {
+        pixels.push(default_pixels);
+    }
+    for frame in &png.frames {
+        pixels.push(frame.image(ihdr)?.rgba_pixels().ok_or_else(unsupported)?);
+    }
+    composite_frames(ihdr.width, ihdr.height, &frames, &pixels, false).ok_or(PngError::InvalidData)
+}
+
+/// Optimize the frames of an APNG, where `images` contains the decoded default image followed by
+/// each of the frames.
+///
+/// Returns the new frames along with the default image and frame images, all converted to 8-bit
+/// RGBA, or None if the frames could not be optimized.
+pub fn optimized_apng(
+    png: &PngData,
+    images: &[PngImage],
+    deadline: &Deadline,
+) -> PngResult<Option<(Vec<Frame>, Vec<PngImage>)>> {
+    let Some(pixels) = images
+        .iter()
+        .map(PngImage::rgba_pixels)
+        .collect::<Option<Vec<_>>>()
+    else {
+        return Ok(None);
+    };
+    let (frames, is_default) = animation_frames(png)?;
+    let skip = usize::from(!is_default);
+
+    let ihdr = &png.raw.ihdr;
+    let Some((mut new_frames, new_pixels)) =
+        optimized_frames(ihdr.width, ihdr.height, &frames, &pixels[skip..], deadline)
+    else {
+        return Ok(None);
+    };
+    if is_default {
+        new_frames.remove(0);
+    }
+    let mut new_images = vec![rgba_image(ihdr.width, ihdr.height, &pixels[0])];
+    for (frame, pixels) in new_frames.iter().zip(&new_pixels[1 - skip..]) {
+        new_images.push(rgba_image(frame.width, frame.height, pixels));
+    }
+    Ok(Some((new_frames, new_images)))
+}
+
+/// Greatest common divisor
+const fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Add two frame delays, returning the sum as a reduced fraction that fits the fcTL fields.
+/// If the exact sum does not fit, it is rounded to the most precise denominator that does.
+fn added_delays(a: (u16, u16), b: (u16, u16)) -> (u16, u16) {
+    // A denominator of 0 is treated as 100
+    let den = |d: u16| if d == 0 { 100 } else { u64::from(d) };
+    let (a_den, b_den) = (den(a.1), den(b.1));
+    let num = u64::from(a.0) * b_den + u64::from(b.0) * a_den;
+    let den = a_den * b_den;
+    let divisor = gcd(num, den).max(1);
+    let (num, den) = (num / divisor, den / divisor);
+    if let (Ok(num), Ok(den)) = (u16::try_from(num), u16::try_from(den)) {
+        return (num, den);
+    }
+    for new_den in [1000, 100, 10, 1] {
+        let new_num = (num * new_den + den / 2) / den;
+        if let Ok(new_num) = u16::try_from(new_num) {
+            return (new_num, new_den as u16);
+        }
+    }
+    (u16::MAX, 1)
+}
+
+/// Retain only the items where `keep` is true
+fn retained<T>(items: Vec<T>, keep: &[bool]) -> Vec<T> {
+    items
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(item, &k)| k.then_some(item))
+        .collect()
+}
+
+/// Remove frames of an APNG whose displayed output is identical to the previous frame, adding their
+/// delay to the previous frame instead.
+///
+/// Returns the number of frames that were removed.
+pub fn merge_duplicate_frames(png: &mut PngData) -> PngResult<usize> {
+    let ihdr = png.raw.ihdr.clone();
+    let (mut frames, is_default) = animation_frames(png)?;
+    let mut images = Vec::with_capacity(frames.len());
+    if is_default {
+        images.push(png.raw.rgba_pixels());
+    }
+    for frame in &png.frames {
+        images.push(frame.image(&ihdr)?.rgba_pixels());
+    }
+    let Some(pixels) = images.into_iter().collect::<Option<Vec<_>>>() else {
+        return Ok(0);
+    };
+    let Some(outputs) = composite_frames(ihdr.width, ihdr.height, &frames, &pixels, true) else {
+        return Ok(0);
+    };
+
+    let mut keep = vec![true; frames.len()];
+    let mut survivor = 0;
+    for i in 1..frames.len() {
+        if outputs[i] == outputs[survivor] {
+            // The survivor's dispose operation must leave the canvas as this frame would have
+            let frame = &frames[i];
+            let prev = &frames[survivor];
+            let same_region = (frame.x_offset, frame.y_offset, frame.width, frame.height)
+                == (prev.x_offset, prev.y_offset, prev.width, prev.height);
+            let dispose_op = match frame.dispose_op {
+                DISPOSE_OP_NONE => Some(DISPOSE_OP_NONE),
+                DISPOSE_OP_PREVIOUS => Some(prev.dispose_op),
+                _ if same_region => Some(DISPOSE_OP_BACKGROUND),
+                _ => None,
+            };
+            if let Some(dispose_op) = dispose_op {
+                let delay = (frame.delay_num, frame.delay_den);
+                let prev = &mut frames[survivor];
+                (prev.delay_num, prev.delay_den) =
+                    added_delays((prev.delay_num, prev.delay_den), delay);
+                prev.dispose_op = dispose_op;
+                keep[i] = false;
+                continue;
+            }
+        }
+        survivor = i;
+    }
+    let removed = keep.iter().filter(|&&k| !k).count();
+    if removed == 0 {
+        return Ok(0);
+    }
+
+    // Verify that the remaining frames display exactly as before
+    let frames = retained(frames, &keep);
+    let expected = retained(outputs, &keep);
+    let pixels = retained(pixels, &keep);
+    let actual = composite_frames(ihdr.width, ihdr.height, &frames, &pixels, true);
+    if actual.as_ref() != Some(&expected) {
+        return Ok(0);
+    }
+
+    let mut frames = frames.into_iter();
+    if is_default {
+        let default_frame = frames.next().unwrap();
+        if let Some(fctl) = png.aux_chunks.iter_mut().find(|c| &c.name == b"fcTL") {
+            fctl.data = default_frame.fctl_data(read_be_u32(&fctl.data[0..4]));
+        }
+    }
+    png.frames = frames.collect();
+    Ok(removed)
+}
+
+/// Validate the structure of an APNG: the default image frame, the frame count in the acTL chunk,
+/// and the region and operations of each frame. Only the headers are checked here; errors in the
+/// frame data are left to be reported when the frames are decoded.
+///
+/// If `fix` is set, problems are reported as warnings and repaired where possible by removing
+/// invalid frames. A wrong frame count is only a warning, as it is corrected when the file is
+/// written.
+pub fn validate_apng(png: &mut PngData, fix: bool) -> PngResult<()> {
+    let report = |err: PngError| {
+        if fix {
+            warn!("{err}, repairing");
+            Ok(())
+        } else {
+            Err(err)
+        }
+    };
+    let has_actl = png.aux_chunks.iter().any(|c| &c.name == b"acTL");
+    let fctl_count = png.aux_chunks.iter().filter(|c| &c.name == b"fcTL").count();
+    if !has_actl {
+        if fctl_count == 0 && png.frames.is_empty() {
+            return Ok(());
+        }
+        // Frames without an acTL chunk would produce an invalid file, so drop them all
+        report(PngError::ChunkMissing("acTL"))?;
+        png.aux_chunks.retain(|c| &c.name != b"fcTL");
+        png.frames.clear();
+        return Ok(());
+    }
+    if fctl_count > 1 {
+        return Err(PngError::APNGOutOfOrder);
+    }
+
+    // Check the frame count before anything is removed
+    let actl = png.aux_chunks.iter().find(|c| &c.name == b"acTL").unwrap();
+    let num_frames = read_be_u32(actl.data.get(0..4).ok_or(PngError::TruncatedData)?);
+    let actual = (png.frames.len() + fctl_count) as u32;
+    if num_frames != actual {
+        warn!("APNG frame count {num_frames} does not match the actual count {actual}, correcting");
+    }
+
+    // The default image frame must cover the whole image
+    let ihdr = png.raw.ihdr.clone();
+    if let Some(pos) = png.aux_chunks.iter().position(|c| &c.name == b"fcTL") {
+        let frame = Frame::from_fctl_data(&png.aux_chunks[pos].data)?;
+        let err = if frame.x_offset != 0
+            || frame.y_offset != 0
+            || frame.width != ihdr.width
+            || frame.height != ihdr.height
+        {
+            Some(PngError::APNGDefaultFrameMismatch)
+        } else if !frame.has_valid_ops() {
+            Some(PngError::APNGInvalidFrameOps(0))
+        } else {
+            None
+        };
+        if let Some(err) = err {
+            // Without its fcTL, the default image is simply excluded from the animation
+            report(err)?;
+            png.aux_chunks.remove(pos);
+        }
+    }
+    let first = png.aux_chunks.iter().filter(|c| &c.name == b"fcTL").count();
+
+    let mut keep = Vec::with_capacity(png.frames.len());
+    for (i, frame) in png.frames.iter().enumerate() {
+        let n = (i + first) as u32;
+        let err = if !frame.fits(ihdr.width, ihdr.height) {
+            Some(PngError::APNGFrameOutOfBounds(n))
+        } else if !frame.has_valid_ops() {
+            Some(PngError::APNGInvalidFrameOps(n))
+        } else {
+            None
+        };
+        keep.push(err.is_none());
+        if let Some(err) = err {
+            report(err)?;
+        }
+    }
+    png.frames = retained(std::mem::take(&mut png.frames), &keep);
+
+    if png.frames.is_empty() && first == 0 {
+        // Nothing remains of the animation
+        warn!("No valid frames remain, the image will become standard PNG");
+        png.aux_chunks.retain(|c| &c.name != b"acTL");
+    }
+    Ok(())
+}
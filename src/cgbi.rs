@@ -0,0 +1,72 @@
+use log::{debug, warn};
+
+use crate::{
+    PngResult,
+    colors::{BitDepth, ColorType},
+    deflate::{Deflater, inflate_raw},
+    error::PngError,
+    filters::FilterStrategy,
+    headers::IhdrData,
+    png::PngImage,
+};
+
+/// Check whether the data is an Apple CgBI ("crushed") PNG, which has a CgBI chunk before IHDR
+#[must_use]
+pub(crate) fn is_cgbi(data: &[u8]) -> bool {
+    data.get(12..16) == Some(b"CgBI")
+}
+
+/// Convert the image data of a CgBI PNG to a standard image
+///
+/// The data is compressed as a raw DEFLATE stream without the zlib wrapper, and truecolor pixels
+/// are stored in BGR(A) order with premultiplied alpha. Returns the image along with standard
+/// compressed data for it.
+pub(crate) fn convert_cgbi(
+    ihdr: IhdrData,
+    idat_data: &[u8],
+    strict: bool,
+) -> PngResult<(PngImage, Vec<u8>)> {
+    let is_truecolor = matches!(ihdr.color_type, ColorType::RGB { .. } | ColorType::RGBA);
+    if is_truecolor && ihdr.bit_depth != BitDepth::Eight {
+        return Err(PngError::new("Unsupported bit depth for CgBI image"));
+    }
+    let raw_data = inflate_raw(idat_data, ihdr.raw_data_size())?;
+    let mut image = PngImage::from_filtered(ihdr, raw_data)?;
+    debug!("Converting Apple CgBI image to standard PNG");
+
+    match image.ihdr.color_type {
+        ColorType::RGB { .. } => {
+            for px in image.data.chunks_exact_mut(3) {
+                px.swap(0, 2);
+            }
+        }
+        ColorType::RGBA => {
+            // Premultiplication maps several colors to the same value, so colors of partially
+            // transparent pixels may not be exactly recovered. Fully transparent pixels have no
+            // color to recover.
+            let mut lossy = 0;
+            for px in image.data.chunks_exact_mut(4) {
+                px.swap(0, 2);
+                let a = u16::from(px[3]);
+                if a == 0 || a == 255 {
+                    continue;
+                }
+                lossy += 1;
+                for c in &mut px[..3] {
+                    *c = ((u16::from(*c) * 255 + a / 2) / a).min(255) as u8;
+                }
+            }
+            if lossy > 0 {
+                if strict {
+                    return Err(PngError::CgBILossy(lossy));
+                }
+                warn!("Colors of {lossy} partially transparent pixels may have lost precision");
+            }
+        }
+        _ => {}
+    }
+
+    let (filtered, _) = image.filter_image(FilterStrategy::NONE, false);
+    let idat_data = Deflater::Libdeflater { compression: 1 }.deflate(&filtered, None)?;
+    Ok((image, idat_data))
+}
@@ -139,6 +139,8 @@ optimization:
     retained by `--keep`, reductions will be disabled.
     caBX, iDOT: Stripped by default. If explicitly retained by `--keep`, optimization will \
     be aborted.
+    Unknown chunks that are not safe to copy: Stripped if the image data changes. If \
+    `--strict-chunks` is used, reductions will be disabled.
 
 The default when --strip is not passed is to keep all chunks that remain valid.",
                        DISPLAY_CHUNKS
@@ -164,6 +166,160 @@ image appearance.")
                 .conflicts_with("strip")
                 .conflicts_with("strip-safe"),
         )
+        .arg(
+            Arg::new("strip-profile")
+                .help("Handle metadata according to a named profile")
+                .long_help("\
+Handle metadata according to a named profile, which may specify chunks to keep or strip, \
+actions for specific chunks, and data to remove from EXIF and XMP. The built-in profiles are:
+
+    web      =>  Keep only chunks that affect the image appearance, removing \
+                 non-colorimetric tags from ICC profiles
+    archive  =>  Keep everything except EXIF and XMP thumbnails
+    print    =>  Keep only pHYs and iCCP
+
+Other profiles may be defined with '--strip-config'. Any other metadata options given \
+explicitly take precedence over the profile.")
+                .long("strip-profile")
+                .value_name("name")
+                .conflicts_with("strip")
+                .conflicts_with("strip-safe")
+                .conflicts_with("keep"),
+        )
+        .arg(
+            Arg::new("strip-config")
+                .help("Load strip profiles from a config file")
+                .long_help("\
+Load strip profiles from a config file, overriding any built-in profiles with the same name. \
+Each profile starts with its name in square brackets, followed by 'key = value' lines, e.g.:
+
+    [social]
+    keep = display,eXIf
+    strip-exif = gps,serial
+    iCCP = convert
+
+The keys 'keep', 'strip', 'strip-exif', 'strip-xmp' and 'keep-xmp' take the same values as \
+the corresponding options. A chunk name may also be given as a key to specify an action for \
+that chunk:
+
+    keep        =>  Keep the chunk
+    drop        =>  Strip the chunk
+    recompress  =>  Keep the chunk, minimizing its data (iCCP, zTXt or iTXt). For iCCP, this \
+                    is equivalent to '--slim-icc'.
+    convert     =>  Replace an iCCP chunk with cICP where possible, as '--icc-to-cicp'
+
+Lines starting with '#' are comments.")
+                .long("strip-config")
+                .value_name("file")
+                .value_parser(value_parser!(PathBuf))
+                .requires("strip-profile"),
+        )
+        .arg(
+            Arg::new("strict-chunks")
+                .help("Disable reductions to keep chunks that are not safe to copy")
+                .long_help("\
+Unknown chunks that are marked as not safe to copy may depend on the image data, and are \
+normally removed if the image data changes. This option instead disables all reductions, so \
+that these chunks remain valid and can be kept.")
+                .long("strict-chunks")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("strip-exif")
+                .help("Remove selected data from EXIF (comma-separated list)")
+                .long_help("\
+Remove selected data from the eXIf chunk, where <list> is a comma-separated list of:
+
+    gps        =>  GPS location data
+    makernote  =>  Manufacturer-specific maker notes
+    serial     =>  Camera body and lens serial numbers
+    thumbnail  =>  The embedded thumbnail image
+    <tag>      =>  A specific tag in hexadecimal, e.g. '0xA430'
+
+E.g. '--strip-exif gps,makernote' will remove location data and maker notes while keeping \
+other data such as the orientation and copyright. The removed data is zeroed in place. If the \
+EXIF data is malformed, the whole chunk is stripped.")
+                .long("strip-exif")
+                .value_name("list"),
+        )
+        .arg(
+            Arg::new("strip-xmp")
+                .help("Remove bulky data from XMP (optional comma-separated list)")
+                .long_help("\
+Remove selected properties from the XMP packet, along with any padding whitespace. <list> is \
+a comma-separated list of qualified property names such as 'xmpMM:History', or namespace \
+prefixes such as 'photoshop' to remove all properties in that namespace.
+
+If <list> is not given, editing history, document ancestors and thumbnails are removed: \
+xmpMM:History, xmpMM:DerivedFrom, xmpMM:Ingredients, xmpMM:Pantry, xmpMM:Manifest, \
+photoshop:DocumentAncestors, xmp:Thumbnails, xap:Thumbnails, xmpGImg
+
+Rights and licensing information (dc:rights, xmpRights, cc, plus) is always kept, along with \
+anything listed in '--keep-xmp'.")
+                .long("strip-xmp")
+                .value_name("list")
+                .num_args(0..=1),
+        )
+        .arg(
+            Arg::new("keep-xmp")
+                .help("Keep XMP properties in the comma-separated list")
+                .long_help("\
+Keep XMP properties or namespaces in the comma-separated list, even if they are selected for \
+removal by '--strip-xmp'.")
+                .long("keep-xmp")
+                .value_name("list")
+                .requires("strip-xmp"),
+        )
+        .arg(
+            Arg::new("set-text")
+                .help("Add a text chunk with the given keyword and value")
+                .long_help("\
+Add a text chunk with the given keyword and value, e.g. '--set-text Copyright=ACME', replacing \
+any existing text chunks with the same keyword. The keyword must be 1-79 printable Latin-1 \
+characters, with no leading, trailing or consecutive spaces. This option may be repeated, and \
+the chunks are added regardless of any stripping.")
+                .long("set-text")
+                .value_name("keyword=value")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("remove-text")
+                .help("Remove text chunks with the given keyword")
+                .long_help("\
+Remove all text chunks (tEXt, zTXt and iTXt) with the given keyword. This option may be \
+repeated.")
+                .long("remove-text")
+                .value_name("keyword")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("slim-icc")
+                .help("Remove non-colorimetric tags from ICC profiles")
+                .long_help("\
+Remove tags that don't affect color rendering from an ICC profile, such as localized \
+descriptions, device information and vendor-private tags. The required description and \
+copyright tags are kept but emptied, and data shared by several tags is stored only once.")
+                .long("slim-icc")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("icc-to-cicp")
+                .help("Use cICP in place of well-known ICC profiles [default mode: replace]")
+                .long_help("\
+Use a cICP chunk in place of an ICC profile that describes a well-known color space: sRGB, \
+Display P3, Rec. 2020, or a profile for BT.2100 PQ/HLG which includes its own cICP values. \
+Standard sRGB profiles that can be replaced with an sRGB chunk are unaffected, even when chunks \
+are not being stripped.
+
+replace  =>  Replace the iCCP chunk with a cICP chunk (default)
+add      =>  Add a cICP chunk, keeping the iCCP chunk for decoders without cICP support")
+                .long("icc-to-cicp")
+                .value_name("mode")
+                .num_args(0..=1)
+                .default_missing_value("replace")
+                .value_parser(["replace", "add"])
+                .hide_possible_values(true),
+        )
         .arg(
             Arg::new("alpha")
                 .help("Perform additional alpha channel optimization")
@@ -176,6 +332,57 @@ transformation and may be unsuitable for some applications.")
                 .long("alpha")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("alpha-threshold")
+                .help("Snap alpha values near 0 or 255 (lossy)")
+                .long_help("\
+Snap alpha values that are within <threshold> of fully transparent or fully opaque to 0 or \
+255 respectively, before performing any reductions. Alpha values such as 1-3 are invisible \
+in practice, but they can prevent the image from being reduced to RGB+tRNS or indexed. \
+This is a lossy transformation. For 16-bit images, the threshold is scaled accordingly.
+
+Combine with '--alpha' to also clean the color values of the newly transparent pixels.")
+                .long("alpha-threshold")
+                .value_name("threshold")
+                .value_parser(0..=127),
+        )
+        .arg(
+            Arg::new("optimize-frames")
+                .help("Crop APNG frames to the changed regions")
+                .long_help("\
+Optimize the frames of animated PNGs by cropping each frame to the region that changed from \
+the previous frame, and selecting the dispose and blend operations that give the smallest \
+result. Unchanged pixels may be made transparent when blending over the previous frame. The \
+displayed animation is guaranteed to be identical, otherwise the frames are left unchanged.")
+                .long("optimize-frames")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("merge-frames")
+                .help("Merge duplicate APNG frames")
+                .long_help("\
+Remove frames of animated PNGs that display identically to the previous frame, adding their \
+delay to the previous frame instead. The frame count and sequence numbers are updated \
+accordingly.")
+                .long("merge-frames")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("export-frames")
+                .help("Export APNG frames as standalone PNGs")
+                .long_help("\
+Instead of optimizing the animation, composite the frames of an animated PNG and export them \
+as standalone optimized PNGs. If <index> is given, only that frame is exported, where 0 is the \
+first frame of the animation. Otherwise all frames are exported.
+
+Each frame is written next to the output file with the frame number appended to the name, e.g. \
+'image.frame0.png', unless a single frame is exported to an explicit '--out' path. A static \
+PNG is treated as having a single frame.")
+                .long("export-frames")
+                .value_name("index")
+                .num_args(0..=1)
+                .value_parser(value_parser!(usize)),
+        )
         .arg(
             Arg::new("interlace")
                 .help("Set PNG interlacing (off, on, keep)")
@@ -210,6 +417,29 @@ losslessly.")
                 .long("scale16")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("lossy")
+                .help("Quantize images with too many colors to a palette (lossy)")
+                .long_help("\
+Quantize RGB/RGBA images that have more than 256 colors to an indexed palette. This is a \
+lossy operation. The quantized image is only used if its quality is at least <quality> \
+(0-100) and it is smaller than the lossless result. If no value is given, the minimum \
+quality is 70.
+
+This option requires oxipng to be built with the 'lossy' feature.")
+                .long("lossy")
+                .value_name("quality")
+                .num_args(0..=1)
+                .default_missing_value("70")
+                .value_parser(0..=100),
+        )
+        .arg(
+            Arg::new("dither")
+                .help("Apply dithering when quantizing with '--lossy'")
+                .long("dither")
+                .action(ArgAction::SetTrue)
+                .requires("lossy"),
+        )
         .arg(
             Arg::new("verbose")
                 .help("Show per-file info (use multiple times for more detail)")
@@ -322,7 +552,7 @@ Do not perform any transformations and do not deinterlace by default.")
                 .help("Do not recompress unless transformations occur")
                 .long_help("\
 Do not recompress IDAT unless required due to transformations. Recompression of other \
-compressed chunks (such as iCCP) will also be disabled. Note that the combination of \
+compressed chunks (such as iCCP and zTXt) will also be disabled. Note that the combination of \
 '--nx' and '--nz' will fully disable all optimization.")
                 .long("nz")
                 .action(ArgAction::SetTrue),
@@ -336,6 +566,46 @@ be processed successfully. The output will always have correct checksums.")
                 .long("fix")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("recover")
+                .help("Recover damaged files [default mode: pad]")
+                .long_help("\
+Recover files that are truncated or have corrupt image data, such as a missing IEND chunk or a \
+bad zlib checksum. As many complete scan lines as possible are salvaged and the rest of the \
+image is completed according to the mode. Trailing data after the IEND chunk is discarded. \
+Each repair is reported as a warning. Combine with '--fix' to also ignore chunk checksums.
+
+pad   =>  Fill the missing scan lines with zeros, which are black or transparent (default)
+crop  =>  Reduce the image height to the recovered scan lines (interlaced images are padded)")
+                .long("recover")
+                .value_name("mode")
+                .num_args(0..=1)
+                .default_missing_value("pad")
+                .value_parser(["pad", "crop"])
+                .hide_possible_values(true),
+        )
+        .arg(
+            Arg::new("strict-cgbi")
+                .help("Reject CgBI images that can't be converted losslessly")
+                .long_help("\
+Apple CgBI (\"crushed\") PNGs are converted to standard PNGs. Colors of partially transparent \
+pixels in these images are premultiplied by their alpha, so cannot always be exactly \
+recovered. This option rejects such images rather than converting them with a loss of \
+precision.")
+                .long("strict-cgbi")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verify")
+                .help("Verify that the output matches the input pixel for pixel")
+                .long_help("\
+Decode the output and check that it matches the input pixel for pixel, including every frame \
+of an animation. If there is any difference, the original file is kept and an error is \
+reported. Colors of fully transparent pixels are not compared, and lossy options are not \
+verified.")
+                .long("verify")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("force")
                 .help("Write the output even if it is larger than the input")
@@ -424,6 +694,31 @@ a 1920x1080 image with 24-bit color depth would be roughly 6MB.")
                 .value_name("bytes")
                 .value_parser(|s: &str| parse_size(s)),
         )
+        .arg(
+            Arg::new("stream-size")
+                .help("Stream images whose decompressed size exceeds this limit")
+                .long_help("\
+Process images whose raw, decompressed image data exceeds this size a few scan lines at a \
+time, rather than decoding them in full. This allows very large images to be optimized with \
+little memory, but the optimization is less thorough: only bit depth, grayscale, alpha and \
+palette reductions are performed, interlacing is not changed, and Zopfli is not used. \
+Animated images are always decoded in full. The value may be specified with a unit suffix \
+such as k, KB, m, MB, etc.")
+                .long("stream-raw-size")
+                .value_name("bytes")
+                .value_parser(|s: &str| parse_size(s)),
+        )
+        .arg(
+            Arg::new("mmap")
+                .help("Memory map input files instead of reading them")
+                .long_help("\
+Memory map input files instead of reading them into memory. This reduces memory usage when \
+optimizing large files. Files must not be modified by other processes while they are being \
+optimized, as this can cause incorrect output or a crash. Requires building with the `mmap` \
+feature.")
+                .long("mmap")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("threads")
                 .help("Number of threads to use [default: num logical CPUs]")
@@ -20,14 +20,29 @@ pub fn inflate(data: &[u8], out_size: usize) -> PngResult<Vec<u8>> {
     let mut dest = vec![0; out_size];
     let len = decompressor
         .zlib_decompress(data, &mut dest)
-        .map_err(|err| match err {
-            DecompressionError::BadData => PngError::InvalidData,
-            DecompressionError::InsufficientSpace => PngError::InflatedDataTooLong(out_size),
-        })?;
+        .map_err(|err| inflate_error(err, out_size))?;
     dest.truncate(len);
     Ok(dest)
 }
 
+/// Decompress a raw DEFLATE stream, without the zlib header and checksum
+pub fn inflate_raw(data: &[u8], out_size: usize) -> PngResult<Vec<u8>> {
+    let mut decompressor = Decompressor::new();
+    let mut dest = vec![0; out_size];
+    let len = decompressor
+        .deflate_decompress(data, &mut dest)
+        .map_err(|err| inflate_error(err, out_size))?;
+    dest.truncate(len);
+    Ok(dest)
+}
+
+const fn inflate_error(err: DecompressionError, out_size: usize) -> PngError {
+    match err {
+        DecompressionError::BadData => PngError::InvalidData,
+        DecompressionError::InsufficientSpace => PngError::InflatedDataTooLong(out_size),
+    }
+}
+
 #[must_use]
 pub fn crc32(data: &[u8]) -> u32 {
     let mut crc = Crc::new();
@@ -1,7 +1,7 @@
 mod deflater;
 use std::{fmt, fmt::Display};
 
-pub use deflater::{crc32, deflate, inflate};
+pub use deflater::{crc32, deflate, inflate, inflate_raw};
 
 use crate::{PngError, PngResult};
 
@@ -1,4 +1,4 @@
 /// List of chunks that affect image display and will be kept when using the `Safe` chunk strip option
-pub const DISPLAY_CHUNKS: [[u8; 4]; 7] = [
-    *b"cICP", *b"iCCP", *b"sRGB", *b"pHYs", *b"acTL", *b"fcTL", *b"fdAT",
+pub const DISPLAY_CHUNKS: [[u8; 4]; 9] = [
+    *b"cICP", *b"mDCV", *b"cLLI", *b"iCCP", *b"sRGB", *b"pHYs", *b"acTL", *b"fcTL", *b"fdAT",
 ];
@@ -5,8 +5,13 @@ use crate::colors::{BitDepth, ColorType};
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum PngError {
+    APNGDefaultFrameMismatch,
+    APNGFrameOutOfBounds(u32),
+    APNGInvalidFrameOps(u32),
     APNGOutOfOrder,
+    APNGSequenceMismatch(u32, u32),
     C2PAMetadataPreventsChanges,
+    CgBILossy(usize),
     ChunkMissing(&'static str),
     ChunkPreventsChanges([u8; 4]),
     CRCMismatch([u8; 4]),
@@ -15,9 +20,11 @@ pub enum PngError {
     InflatedDataTooLong(usize),
     InvalidData,
     InvalidDepthForType(BitDepth, ColorType),
+    InvalidKeyword(String),
     NotPNG,
     ReadFailed(String, std::io::Error),
     TruncatedData,
+    VerificationFailed(u32, Option<usize>),
     WriteFailed(String, std::io::Error),
     Other(Box<str>),
 }
@@ -29,10 +36,29 @@ impl fmt::Display for PngError {
     #[cold]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
+            Self::APNGDefaultFrameMismatch => f.write_str(
+                "APNG default image frame does not match the image size; May be recoverable by using --fix",
+            ),
+            Self::APNGFrameOutOfBounds(n) => write!(
+                f,
+                "APNG frame {n} lies outside the image; May be recoverable by using --fix"
+            ),
+            Self::APNGInvalidFrameOps(n) => write!(
+                f,
+                "APNG frame {n} has an invalid dispose or blend operation; May be recoverable by using --fix"
+            ),
             Self::APNGOutOfOrder => f.write_str("APNG chunks are out of order"),
+            Self::APNGSequenceMismatch(expected, actual) => write!(
+                f,
+                "APNG sequence number {actual} does not match the expected {expected}; May be recoverable by using --fix"
+            ),
             Self::C2PAMetadataPreventsChanges => f.write_str(
                 "The image contains C2PA manifest that would be invalidated by any file changes",
             ),
+            Self::CgBILossy(n) => write!(
+                f,
+                "CgBI image has {n} partially transparent pixels whose colors cannot be exactly recovered"
+            ),
             Self::ChunkMissing(s) => write!(f, "Chunk {s} missing or empty"),
             Self::ChunkPreventsChanges(ref c) => write!(
                 f,
@@ -57,9 +83,18 @@ impl fmt::Display for PngError {
             Self::InvalidDepthForType(d, ref c) => {
                 write!(f, "Invalid bit depth {d} for color type {c}")
             }
+            Self::InvalidKeyword(ref k) => write!(f, "Invalid text keyword {k:?}"),
             Self::NotPNG => f.write_str("Invalid header detected; Not a PNG file"),
             Self::ReadFailed(ref s, ref e) => write!(f, "Failed to read from {s}: {e}"),
             Self::TruncatedData => f.write_str("Missing data in the file; the file is truncated"),
+            Self::VerificationFailed(frame, Some(pixel)) => write!(
+                f,
+                "Output verification failed: frame {frame} differs from the input at pixel {pixel}"
+            ),
+            Self::VerificationFailed(frame, None) => write!(
+                f,
+                "Output verification failed: frame {frame} could not be decoded or has a different size"
+            ),
             Self::WriteFailed(ref s, ref e) => write!(f, "Failed to write to {s}: {e}"),
             Self::Other(ref s) => f.write_str(s),
         }
@@ -25,7 +25,7 @@ pub(crate) struct Candidate {
     pub image: Arc<PngImage>,
     pub idat_data: Option<Vec<u8>>,
     pub estimated_output_size: usize,
-    /// The input filter, which is retained for printing and for APNG frames.
+    /// The input filter, which is retained for printing.
     pub filter: FilterStrategy,
     /// The filter returned by the filter function, which may be Predefined.
     /// Use this for the next round to avoid recomputing the filter.
@@ -0,0 +1,280 @@
+use std::ops::Range;
+
+use log::{debug, warn};
+
+use crate::headers::Chunk;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Data that can be selectively removed from an eXIf chunk
+pub enum ExifStrip {
+    /// GPS location data (the whole GPS IFD)
+    Gps,
+    /// Manufacturer-specific maker notes
+    MakerNote,
+    /// Camera body and lens serial numbers
+    SerialNumbers,
+    /// The embedded thumbnail image (IFD1)
+    Thumbnail,
+    /// A specific tag, in any IFD
+    Tag(u16),
+}
+
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const INTEROP_IFD: u16 = 0xA005;
+const MAKER_NOTE: u16 = 0x927C;
+const SERIAL_NUMBERS: [u16; 3] = [0xA431, 0xA435, 0xC62F];
+const THUMBNAIL_OFFSET: u16 = 0x0201;
+const THUMBNAIL_LENGTH: u16 = 0x0202;
+
+impl ExifStrip {
+    /// Whether the given tag should be removed
+    fn matches(self, tag: u16) -> bool {
+        match self {
+            Self::Gps => tag == GPS_IFD,
+            Self::MakerNote => tag == MAKER_NOTE,
+            Self::SerialNumbers => SERIAL_NUMBERS.contains(&tag),
+            Self::Thumbnail => false,
+            Self::Tag(t) => tag == t,
+        }
+    }
+}
+
+/// An IFD entry, along with the location of its value if stored outside the entry
+struct Entry {
+    tag: u16,
+    raw: [u8; 12],
+    value: Option<Range<usize>>,
+}
+
+/// The TIFF structure of EXIF data
+struct Tiff {
+    data: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Tiff {
+    fn new(data: &[u8]) -> Option<Self> {
+        let big_endian = match data.get(0..4)? {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => return None,
+        };
+        Some(Self {
+            data: data.to_vec(),
+            big_endian,
+        })
+    }
+
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let bytes = *self.data.get(pos..)?.first_chunk()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let bytes = *self.data.get(pos..)?.first_chunk()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn set_u16(&mut self, pos: usize, value: u16) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        self.data[pos..pos + 2].copy_from_slice(&bytes);
+    }
+
+    fn set_u32(&mut self, pos: usize, value: u32) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        self.data[pos..pos + 4].copy_from_slice(&bytes);
+    }
+
+    /// Read the entries of the IFD at the given offset, checking that it lies within the data
+    fn read_ifd(&self, offset: usize) -> Option<Vec<Entry>> {
+        let count = self.u16(offset)? as usize;
+        // The entries are followed by the offset of the next IFD
+        if offset.checked_add(2 + count * 12 + 4)? > self.data.len() {
+            return None;
+        }
+        (0..count)
+            .map(|i| {
+                let pos = offset + 2 + i * 12;
+                let size = type_size(self.u16(pos + 2)?)
+                    .and_then(|s| s.checked_mul(self.u32(pos + 4)? as usize));
+                // Values of up to 4 bytes are stored in the entry itself
+                let value = match size {
+                    Some(size) if size > 4 => {
+                        let start = self.u32(pos + 8)? as usize;
+                        let end = start.checked_add(size)?;
+                        (end <= self.data.len()).then_some(start..end)
+                    }
+                    _ => None,
+                };
+                Some(Entry {
+                    tag: self.u16(pos)?,
+                    raw: *self.data[pos..].first_chunk().unwrap(),
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    /// Get the offset stored in the entry with the given tag, if present
+    fn pointer(&self, entries: &[Entry], tag: u16) -> Option<usize> {
+        let entry = entries.iter().find(|e| e.tag == tag)?;
+        let bytes = *entry.raw[8..].first_chunk().unwrap();
+        let offset = if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        };
+        Some(offset as usize)
+    }
+
+    /// Remove the entries of an IFD with matching tags, zeroing their values
+    ///
+    /// Returns the number of entries removed.
+    fn remove_entries(&mut self, offset: usize, remove: impl Fn(u16) -> bool) -> Option<usize> {
+        let entries = self.read_ifd(offset)?;
+        let next = self.u32(offset + 2 + entries.len() * 12)?;
+        let (kept, removed): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| !remove(e.tag));
+        for range in removed.iter().filter_map(|e| e.value.clone()) {
+            self.data[range].fill(0);
+        }
+        // Rewrite the IFD with the remaining entries, zeroing the space left at the end
+        self.set_u16(offset, kept.len() as u16);
+        let mut pos = offset + 2;
+        for entry in &kept {
+            self.data[pos..pos + 12].copy_from_slice(&entry.raw);
+            pos += 12;
+        }
+        self.set_u32(pos, next);
+        pos += 4;
+        self.data[pos..pos + removed.len() * 12].fill(0);
+        Some(removed.len())
+    }
+
+    /// Zero an IFD along with all of its values
+    fn clear_ifd(&mut self, offset: usize) -> Option<()> {
+        let entries = self.read_ifd(offset)?;
+        for range in entries.iter().filter_map(|e| e.value.clone()) {
+            self.data[range].fill(0);
+        }
+        self.data[offset..offset + 2 + entries.len() * 12 + 4].fill(0);
+        Some(())
+    }
+}
+
+/// The size in bytes of a TIFF field type
+const fn type_size(field_type: u16) -> Option<usize> {
+    match field_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+/// Remove the selected data from EXIF, returning None if the data could not be parsed
+fn sanitized_exif(data: &[u8], strip: &[ExifStrip]) -> Option<Vec<u8>> {
+    let mut tiff = Tiff::new(data)?;
+    let remove = |tag| strip.iter().any(|s| s.matches(tag));
+
+    let ifd0 = tiff.u32(4)? as usize;
+    let entries = tiff.read_ifd(ifd0)?;
+    let exif = tiff.pointer(&entries, EXIF_IFD);
+    let gps = tiff.pointer(&entries, GPS_IFD);
+    let interop = match exif {
+        Some(offset) => tiff.pointer(&tiff.read_ifd(offset)?, INTEROP_IFD),
+        None => None,
+    };
+    let ifd1 = Some(tiff.u32(ifd0 + 2 + entries.len() * 12)? as usize).filter(|&o| o != 0);
+
+    // Sub-IFDs are cleared entirely when the pointer to them is removed
+    let mut cleared = Vec::new();
+    let mut sub_ifds = Vec::new();
+    for (offset, tag) in [(exif, EXIF_IFD), (gps, GPS_IFD), (interop, INTEROP_IFD)] {
+        let Some(offset) = offset else {
+            continue;
+        };
+        // The interop IFD is also cleared along with the EXIF IFD containing it
+        if remove(tag) || (tag == INTEROP_IFD && exif.is_some_and(|o| cleared.contains(&o))) {
+            cleared.push(offset);
+        } else {
+            sub_ifds.push(offset);
+        }
+    }
+    if let Some(offset) = ifd1 {
+        if strip.contains(&ExifStrip::Thumbnail) {
+            // The thumbnail data itself is located by a pair of tags in IFD1
+            let entries = tiff.read_ifd(offset)?;
+            let start = tiff.pointer(&entries, THUMBNAIL_OFFSET);
+            let len = tiff.pointer(&entries, THUMBNAIL_LENGTH);
+            if let (Some(start), Some(len)) = (start, len) {
+                let end = start.checked_add(len)?;
+                tiff.data.get_mut(start..end)?.fill(0);
+            }
+            cleared.push(offset);
+        } else {
+            sub_ifds.push(offset);
+        }
+    }
+
+    let mut removed = tiff.remove_entries(ifd0, remove)?;
+    for &offset in &sub_ifds {
+        removed += tiff.remove_entries(offset, remove)?;
+    }
+    for &offset in &cleared {
+        tiff.clear_ifd(offset)?;
+    }
+    if ifd1.is_some_and(|o| cleared.contains(&o)) {
+        // Unlink IFD1 from IFD0
+        let count = tiff.u16(ifd0)? as usize;
+        tiff.set_u32(ifd0 + 2 + count * 12, 0);
+    }
+    if removed > 0 || !cleared.is_empty() {
+        debug!(
+            "Removed {removed} EXIF tags and {} EXIF IFDs",
+            cleared.len()
+        );
+    }
+    Some(tiff.data)
+}
+
+/// Remove the selected data from any eXIf chunk, stripping the chunk entirely if it is malformed.
+/// Returns whether anything was changed.
+pub(crate) fn sanitize_exif(aux_chunks: &mut Vec<Chunk>, strip: &[ExifStrip]) -> bool {
+    if strip.is_empty() {
+        return false;
+    }
+    let mut changed = false;
+    aux_chunks.retain_mut(|chunk| {
+        if &chunk.name != b"eXIf" {
+            return true;
+        }
+        if let Some(data) = sanitized_exif(&chunk.data, strip) {
+            changed |= data != chunk.data;
+            chunk.data = data;
+            true
+        } else {
+            warn!("Stripping eXIf chunk which could not be parsed");
+            changed = true;
+            false
+        }
+    });
+    changed
+}
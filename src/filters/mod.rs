@@ -2,6 +2,7 @@ use std::{fmt, fmt::Display, mem::transmute};
 
 mod strategies;
 pub use strategies::FilterStrategy;
+use strategies::StrategyEvaluator;
 
 /// PNG delta filters
 #[repr(u8)]
@@ -239,6 +240,110 @@ impl RowFilter {
     }
 }
 
+/// Filters the scan lines of an image one at a time according to a filter strategy
+pub(crate) struct LineFilter {
+    strategy: FilterStrategy,
+    evaluator: Option<Box<dyn StrategyEvaluator>>,
+    bpp: usize,
+    alpha_bytes: usize,
+    prev_line: Vec<u8>,
+    prev_pass: Option<u8>,
+    line_index: usize,
+    // For heuristic strategies, keep track of the actual filter used for each line
+    filters_used: Vec<RowFilter>,
+}
+
+impl LineFilter {
+    pub fn new(strategy: FilterStrategy, bpp: usize, alpha_bytes: usize) -> Self {
+        Self {
+            evaluator: strategy.evaluator(),
+            strategy,
+            bpp,
+            alpha_bytes,
+            prev_line: Vec::new(),
+            prev_pass: None,
+            line_index: 0,
+            filters_used: Vec::new(),
+        }
+    }
+
+    /// Filter the next unfiltered line, appending it to the output. Heuristic strategies may
+    /// examine the preceding output as context, so this should contain at least the most recent
+    /// lines.
+    pub fn filter_line(&mut self, data: &[u8], pass: Option<u8>, output: &mut Vec<u8>) {
+        let (bpp, alpha_bytes) = (self.bpp, self.alpha_bytes);
+        if self.prev_pass != pass || self.prev_line.is_empty() {
+            self.prev_line = vec![0; data.len()];
+            self.prev_pass = pass;
+        }
+        let i = self.line_index;
+        self.line_index += 1;
+        // Alpha optimisation may alter the line data, so we need a mutable copy of it
+        let mut line_data = data.to_vec();
+
+        if let FilterStrategy::Basic(filter) = self.strategy {
+            // Standard filters
+            filter.filter_line(bpp, &mut line_data, &self.prev_line, output, alpha_bytes);
+            self.prev_line = line_data;
+            return;
+        } else if let FilterStrategy::Predefined(lines) = &self.strategy {
+            // Predefined filter for each line
+            let filter = lines.get(i).unwrap_or(&RowFilter::None);
+            filter.filter_line(bpp, &mut line_data, &self.prev_line, output, alpha_bytes);
+            self.prev_line = line_data;
+            return;
+        }
+
+        // Heuristic filter selection strategies
+
+        let mut best_filter = RowFilter::None;
+        if line_data.iter().all(|&x| x == 0) {
+            // Assume None if the line is all zeros
+            best_filter.filter_line(bpp, &mut line_data, &self.prev_line, output, alpha_bytes);
+            self.prev_line = line_data;
+            self.filters_used.push(best_filter);
+            return;
+        }
+
+        let line_len = data.len() + 1;
+        let mut best_line = vec![0; line_len];
+        let mut best_line_raw = Vec::with_capacity(data.len());
+        let offset = output.len();
+        let evaluator = self.evaluator.as_mut().unwrap();
+        evaluator.reset(line_len);
+        for f in RowFilter::ALL {
+            f.filter_line(bpp, &mut line_data, &self.prev_line, output, alpha_bytes);
+            if evaluator.evaluate(output, offset) {
+                best_line.clone_from_slice(&output[offset..]);
+                best_line_raw.clone_from(&line_data);
+                best_filter = f;
+            }
+            output.truncate(offset);
+        }
+        output.extend_from_slice(&best_line);
+        self.prev_line = best_line_raw;
+        self.filters_used.push(best_filter);
+    }
+
+    /// The number of bytes of preceding output that may be examined when filtering a line of
+    /// the given length
+    pub const fn context_len(&self, line_len: usize) -> usize {
+        match self.strategy {
+            FilterStrategy::Brute { num_lines, .. } => num_lines * (line_len + 1),
+            _ => 0,
+        }
+    }
+
+    /// Finish filtering, returning a strategy that reproduces the filters that were used
+    pub fn finish(self) -> FilterStrategy {
+        if self.filters_used.is_empty() {
+            self.strategy
+        } else {
+            FilterStrategy::Predefined(self.filters_used)
+        }
+    }
+}
+
 fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
     let p = i32::from(a) + i32::from(b) - i32::from(c);
     let pa = (p - i32::from(a)).abs();
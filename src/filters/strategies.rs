@@ -64,7 +64,7 @@ impl Display for FilterStrategy {
     }
 }
 
-pub(crate) trait StrategyEvaluator {
+pub(crate) trait StrategyEvaluator: Send {
     /// Reset any state for a new line, if necessary.
     fn reset(&mut self, _line_len: usize) {}
     /// Evaluate the output of a filter attempt, returning true if it's the best so far.
@@ -8,9 +8,14 @@ use crate::{
     deflate::{crc32, inflate},
     display_chunks::DISPLAY_CHUNKS,
     error::PngError,
+    exif::sanitize_exif,
+    icc::{CicpMode, cicp_for_icc},
+    png::PngImage,
+    text::recompress_text_chunks,
+    xmp::sanitize_xmp,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 /// Headers from the IHDR chunk of the image
 pub struct IhdrData {
     /// The width of the image in pixels
@@ -70,7 +75,44 @@ pub struct Chunk {
     pub data: Vec<u8>,
 }
 impl Chunk {
-    const REDUCTION_CONFLICTS: [[u8; 4]; 3] = [*b"bKGD", *b"sBIT", *b"hIST"];
+    pub(crate) const REDUCTION_CONFLICTS: [[u8; 4]; 3] = [*b"bKGD", *b"sBIT", *b"hIST"];
+
+    /// Ancillary chunks defined by the PNG specification and its registered extensions. The pCAL
+    /// and dSIG chunks are not included, as they are invalidated by changes to the image data and
+    /// are not updated, so they must be treated like unknown chunks that are not safe to copy.
+    pub(crate) const KNOWN: [[u8; 4]; 27] = [
+        *b"bKGD", *b"cHRM", *b"cICP", *b"cLLI", *b"eXIf", *b"gAMA", *b"hIST", *b"iCCP", *b"iTXt",
+        *b"mDCV", *b"pHYs", *b"sBIT", *b"sPLT", *b"sRGB", *b"sTER", *b"tEXt", *b"tIME", *b"tRNS",
+        *b"zTXt", *b"acTL", *b"fcTL", *b"fdAT", *b"oFFs", *b"sCAL", *b"gIFg", *b"gIFt", *b"gIFx",
+    ];
+
+    /// Ancillary chunks that must come before PLTE and IDAT
+    pub(crate) const BEFORE_PLTE: [[u8; 4]; 8] = [
+        *b"cHRM", *b"cICP", *b"gAMA", *b"iCCP", *b"mDCV", *b"cLLI", *b"sBIT", *b"sRGB",
+    ];
+
+    /// Ancillary chunks that must come after PLTE (if present) and before IDAT. The tRNS chunk is
+    /// also in this category, but is stored with the critical chunks.
+    pub(crate) const AFTER_PLTE: [[u8; 4]; 2] = [*b"bKGD", *b"hIST"];
+
+    /// Other ancillary chunks that must come before IDAT
+    pub(crate) const BEFORE_IDAT: [[u8; 4]; 7] = [
+        *b"pHYs", *b"sPLT", *b"oFFs", *b"pCAL", *b"sCAL", *b"sTER", *b"acTL",
+    ];
+
+    /// Ancillary chunks that may appear at most once
+    pub(crate) const SINGLETONS: [[u8; 4]; 18] = [
+        *b"cHRM", *b"cICP", *b"gAMA", *b"iCCP", *b"mDCV", *b"cLLI", *b"sBIT", *b"sRGB", *b"bKGD",
+        *b"hIST", *b"pHYs", *b"oFFs", *b"pCAL", *b"sCAL", *b"sTER", *b"acTL", *b"eXIf", *b"tIME",
+    ];
+
+    /// Whether this is an unknown ancillary chunk whose safe-to-copy bit is not set, meaning it
+    /// may depend on the image data and must be dropped if the critical chunks are changed
+    pub(crate) fn is_unsafe_to_copy(&self) -> bool {
+        self.name[0].is_ascii_lowercase()
+            && self.name[3].is_ascii_uppercase()
+            && !Self::KNOWN.contains(&self.name)
+    }
 }
 
 /// [`Options`][crate::Options] to use when stripping chunks (metadata)
@@ -210,6 +252,59 @@ fn palette_to_rgba(
     Ok(palette)
 }
 
+/// Check the ancillary chunks against the ordering and multiplicity rules of the PNG spec, given
+/// the position in `aux_chunks` at which the PLTE chunk was read. Chunks found after IDAT that
+/// must come before it are moved, and repeated chunks that may appear only once are removed,
+/// keeping the first. Chunks on the wrong side of PLTE are placed correctly by the output.
+///
+/// Returns true if any problems were found.
+pub fn normalize_chunk_order(aux_chunks: &mut Vec<Chunk>, plte_position: Option<usize>) -> bool {
+    let idat_position = aux_chunks.iter().position(|c| &c.name == b"IDAT");
+    let mut seen = IndexSet::new();
+    let mut moved = Vec::new();
+    let mut fixed = false;
+    let mut index = 0;
+    aux_chunks.retain(|chunk| {
+        let after_plte = plte_position.is_some_and(|p| index >= p);
+        let after_idat = idat_position.is_some_and(|p| index > p);
+        index += 1;
+        let name = std::str::from_utf8(&chunk.name).unwrap_or_default();
+        if Chunk::SINGLETONS.contains(&chunk.name) && !seen.insert(chunk.name) {
+            warn!("Removing duplicate {name} chunk");
+            fixed = true;
+            return false;
+        }
+        let before_idat = [
+            Chunk::BEFORE_PLTE.as_slice(),
+            &Chunk::AFTER_PLTE,
+            &Chunk::BEFORE_IDAT,
+        ]
+        .iter()
+        .any(|names| names.contains(&chunk.name));
+        if Chunk::BEFORE_PLTE.contains(&chunk.name) && after_plte {
+            warn!("Moving {name} chunk before PLTE");
+            fixed = true;
+        } else if Chunk::AFTER_PLTE.contains(&chunk.name) && plte_position.is_some() && !after_plte
+        {
+            warn!("Moving {name} chunk after PLTE");
+            fixed = true;
+        } else if before_idat && after_idat {
+            warn!("Moving {name} chunk before IDAT");
+            fixed = true;
+        }
+        // The output places chunks correctly relative to PLTE, but those after IDAT must be moved
+        if before_idat && after_idat {
+            moved.push(chunk.clone());
+            return false;
+        }
+        true
+    });
+    if let Some(idat) = aux_chunks.iter().position(|c| &c.name == b"IDAT") {
+        aux_chunks.splice(idat..idat, moved);
+    }
+    fixed
+}
+
 #[inline]
 pub fn read_be_u16(bytes: &[u8]) -> u16 {
     u16::from_be_bytes(bytes.try_into().unwrap())
@@ -291,14 +386,202 @@ pub fn srgb_rendering_intent(icc_data: &[u8]) -> Option<u8> {
     }
 }
 
+/// Get the data of each tag in an ICC profile
+pub fn profile_tags(icc: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    let count = read_be_u32(icc.get(128..132)?) as usize;
+    (0..count)
+        .map(|i| {
+            let entry = icc.get(132 + i * 12..144 + i * 12)?;
+            let offset = read_be_u32(&entry[4..8]) as usize;
+            let size = read_be_u32(&entry[8..12]) as usize;
+            let data = icc.get(offset..offset.checked_add(size)?)?;
+            Some((entry[0..4].try_into().unwrap(), data))
+        })
+        .collect()
+}
+
+/// Tags which may affect color rendering, and so are kept when slimming a profile
+const COLORIMETRIC_TAGS: [&[u8; 4]; 40] = [
+    b"A2B0", b"A2B1", b"A2B2", b"B2A0", b"B2A1", b"B2A2", b"D2B0", b"D2B1", b"D2B2", b"D2B3",
+    b"B2D0", b"B2D1", b"B2D2", b"B2D3", b"rXYZ", b"gXYZ", b"bXYZ", b"rTRC", b"gTRC", b"bTRC",
+    b"kTRC", b"wtpt", b"bkpt", b"chad", b"chrm", b"cicp", b"lumi", b"gamt", b"pre0", b"pre1",
+    b"pre2", b"clro", b"clrt", b"clot", b"ciis", b"ncl2", b"rig0", b"rig2", b"resp", b"ncol",
+];
+
+/// An empty version 2 textDescriptionType, with a null ASCII string and empty Unicode and
+/// ScriptCode strings
+const EMPTY_TEXT_DESCRIPTION: [u8; 91] = {
+    let mut desc = [0; 91];
+    let header = b"desc\0\0\0\0\0\0\0\x01";
+    let mut i = 0;
+    while i < header.len() {
+        desc[i] = header[i];
+        i += 1;
+    }
+    desc
+};
+
+/// Remove tags that don't affect color rendering from an ICC profile, such as localized
+/// descriptions, device information and vendor-private tags. Data shared by several tags is
+/// stored only once. The required description and copyright tags are kept, but emptied.
+///
+/// Returns None if the profile could not be parsed or would not be reduced.
+pub fn slimmed_icc(icc: &[u8]) -> Option<Vec<u8>> {
+    if icc.get(36..40)? != b"acsp" {
+        return None;
+    }
+    let tags = profile_tags(icc)?;
+
+    // Version 4 profiles use multiLocalizedUnicodeType (with a single empty record) for both
+    // text tags, while version 2 profiles use textDescriptionType and textType
+    let (empty_desc, empty_cprt): (&[u8], &[u8]) = if icc[8] >= 4 {
+        let mluc = b"mluc\0\0\0\0\0\0\0\x01\0\0\0\x0cenUS\0\0\0\0\0\0\0\x1c";
+        (mluc, mluc)
+    } else {
+        (&EMPTY_TEXT_DESCRIPTION, b"text\0\0\0\0\0")
+    };
+    let kept: Vec<_> = tags
+        .iter()
+        .filter_map(|&(sig, data)| match &sig {
+            b"desc" => Some((sig, empty_desc)),
+            b"cprt" => Some((sig, empty_cprt)),
+            s if COLORIMETRIC_TAGS.contains(&s) => Some((sig, data)),
+            _ => None,
+        })
+        .collect();
+
+    let mut out = icc[..128].to_vec();
+    out.extend((kept.len() as u32).to_be_bytes());
+    let data_start = out.len() + kept.len() * 12;
+    let mut tag_data = Vec::new();
+    let mut written: Vec<(&[u8], usize)> = Vec::new();
+    for &(sig, data) in &kept {
+        let offset = if let Some(&(_, offset)) = written.iter().find(|(d, _)| *d == data) {
+            offset
+        } else {
+            let offset = data_start + tag_data.len();
+            tag_data.extend(data);
+            // Tag data must be aligned to 4 bytes
+            tag_data.resize(tag_data.len().next_multiple_of(4), 0);
+            written.push((data, offset));
+            offset
+        };
+        out.extend(sig);
+        out.extend((offset as u32).to_be_bytes());
+        out.extend((data.len() as u32).to_be_bytes());
+    }
+    out.append(&mut tag_data);
+    if out.len() >= icc.len() {
+        return None;
+    }
+    let size = out.len() as u32;
+    out[0..4].copy_from_slice(&size.to_be_bytes());
+    // The profile ID is an MD5 hash of the profile, which is no longer valid
+    out[84..100].fill(0);
+    Some(out)
+}
+
+/// Transfer functions in a cICP chunk that indicate HDR content: PQ (SMPTE ST 2084) and HLG
+/// (ARIB STD-B67)
+const HDR_TRANSFER_FUNCTIONS: [u8; 2] = [16, 18];
+
+/// Check that the payload of an HDR chunk (cICP, mDCV or cLLI) is valid
+fn valid_hdr_chunk(chunk: &Chunk) -> bool {
+    match &chunk.name {
+        // PNG only permits RGB matrix coefficients, and the full range flag must be 0 or 1
+        b"cICP" => chunk.data.len() == 4 && chunk.data[2] == 0 && chunk.data[3] <= 1,
+        // The maximum luminance must be greater than the minimum
+        b"mDCV" => {
+            chunk.data.len() == 24
+                && read_be_u32(&chunk.data[16..20]) > read_be_u32(&chunk.data[20..24])
+        }
+        b"cLLI" => chunk.data.len() == 8,
+        _ => true,
+    }
+}
+
+/// The gAMA value for sRGB, as recommended by the PNG specification
+const SRGB_GAMMA: u32 = 45455;
+/// The cHRM values for sRGB: white point, red, green and blue chromaticities
+const SRGB_CHROMATICITIES: [u32; 8] = [31270, 32900, 64000, 33000, 30000, 60000, 15000, 6000];
+
+/// Check whether a gAMA or cHRM chunk matches sRGB, allowing for rounding differences
+fn is_srgb_equivalent(chunk: &Chunk) -> bool {
+    let values: Vec<_> = chunk.data.chunks_exact(4).map(read_be_u32).collect();
+    match &chunk.name {
+        b"gAMA" => values.len() == 1 && values[0].abs_diff(SRGB_GAMMA) <= 5,
+        b"cHRM" => {
+            values.len() == 8
+                && values
+                    .iter()
+                    .zip(SRGB_CHROMATICITIES)
+                    .all(|(&v, s)| v.abs_diff(s) <= 100)
+        }
+        _ => false,
+    }
+}
+
+/// Remove gAMA and cHRM chunks that are overridden by a color space chunk, unless they are to be
+/// kept, or collapse an sRGB-equivalent pair of them into an sRGB chunk
+fn normalize_gamma_chromaticities(aux_chunks: &mut Vec<Chunk>, opts: &Options) {
+    if opts.strip == StripChunks::None {
+        return;
+    }
+    let is_gamma_chrm = |c: &Chunk| &c.name == b"gAMA" || &c.name == b"cHRM";
+    let overridden_by = aux_chunks.iter().find(|c| match &c.name {
+        b"sRGB" | b"iCCP" => true,
+        b"cICP" => valid_hdr_chunk(c),
+        _ => false,
+    });
+    if let Some(chunk) = overridden_by {
+        let name = std::str::from_utf8(&chunk.name).unwrap().to_owned();
+        aux_chunks.retain(|c| {
+            if is_gamma_chrm(c) && !opts.strip.keep(&c.name) {
+                trace!(
+                    "Removing {} chunk as it is overridden by {name} chunk",
+                    std::str::from_utf8(&c.name).unwrap()
+                );
+                return false;
+            }
+            true
+        });
+        return;
+    }
+    let gama = aux_chunks.iter().position(|c| &c.name == b"gAMA");
+    let chrm = aux_chunks.iter().position(|c| &c.name == b"cHRM");
+    if let (Some(gama), Some(chrm)) = (gama, chrm) {
+        if opts.strip.keep(b"sRGB")
+            && is_srgb_equivalent(&aux_chunks[gama])
+            && is_srgb_equivalent(&aux_chunks[chrm])
+        {
+            trace!("Replacing gAMA and cHRM chunks with equivalent sRGB chunk");
+            // Use the perceptual rendering intent, as the chunks don't specify one
+            aux_chunks[gama] = Chunk {
+                name: *b"sRGB",
+                data: vec![0],
+            };
+            aux_chunks.remove(chrm);
+        }
+    }
+}
+
 /// Process aux chunks and potentially adjust options before optimizing
-pub fn preprocess_chunks(aux_chunks: &mut Vec<Chunk>, opts: &mut Options) {
+///
+/// Returns whether any EXIF data was stripped, in which case the output should be kept even if it
+/// is not smaller than the input
+pub fn preprocess_chunks(aux_chunks: &mut Vec<Chunk>, opts: &mut Options) -> bool {
+    normalize_gamma_chromaticities(aux_chunks, opts);
+
     let has_srgb = aux_chunks.iter().any(|c| &c.name == b"sRGB");
     // Grayscale conversion should not be performed if the image is not in the sRGB colorspace
     // An sRGB profile would need to be stripped on conversion, so disallow if stripping is disabled
-    let mut allow_grayscale = !has_srgb || opts.strip != StripChunks::None;
+    // A remaining cHRM chunk with primaries that differ from sRGB also prevents conversion
+    let mut allow_grayscale = (!has_srgb || opts.strip != StripChunks::None)
+        && !aux_chunks
+            .iter()
+            .any(|c| &c.name == b"cHRM" && !is_srgb_equivalent(c));
 
-    if let Some(iccp_idx) = aux_chunks.iter().position(|c| &c.name == b"iCCP") {
+    if let Some(mut iccp_idx) = aux_chunks.iter().position(|c| &c.name == b"iCCP") {
         allow_grayscale = false;
         // See if we can replace an iCCP chunk with an sRGB chunk
         let may_replace_iccp = opts.strip != StripChunks::None && opts.strip.keep(b"sRGB");
@@ -308,11 +591,25 @@ pub fn preprocess_chunks(aux_chunks: &mut Vec<Chunk>, opts: &mut Options) {
             aux_chunks.remove(iccp_idx);
             allow_grayscale = true;
         } else if let Some(icc) = extract_icc(&aux_chunks[iccp_idx], opts.max_decompressed_size) {
-            let intent = if may_replace_iccp {
-                srgb_rendering_intent(&icc)
-            } else {
-                None
-            };
+            let srgb_intent = srgb_rendering_intent(&icc);
+            let intent = srgb_intent.filter(|_| may_replace_iccp);
+            // A well-known profile may be expressed by a cICP chunk, unless one is already present
+            // Profiles that are sRGB are left to the sRGB chunk, even if they can't be replaced
+            let cicp = opts
+                .icc_to_cicp
+                .filter(|_| {
+                    srgb_intent.is_none()
+                        && opts.strip.keep(b"cICP")
+                        && !aux_chunks.iter().any(|c| &c.name == b"cICP")
+                })
+                .and_then(|mode| Some((mode, cicp_for_icc(&icc)?)))
+                .map(|(mode, data)| {
+                    let chunk = Chunk {
+                        name: *b"cICP",
+                        data: data.to_vec(),
+                    };
+                    (mode, chunk)
+                });
             // sRGB-like profile can be replaced with an sRGB chunk with the same rendering intent
             if let Some(intent) = intent {
                 trace!("Replacing iCCP chunk with equivalent sRGB chunk");
@@ -321,34 +618,128 @@ pub fn preprocess_chunks(aux_chunks: &mut Vec<Chunk>, opts: &mut Options) {
                     data: vec![intent],
                 };
                 allow_grayscale = true;
-            } else if opts.idat_recoding {
-                // Try recompressing the profile
-                let cur_len = aux_chunks[iccp_idx].data.len();
-                if let Ok(iccp) = make_iccp(&icc, opts.deflater, Some(cur_len - 1)) {
+            } else if let Some((CicpMode::Replace, cicp)) = cicp {
+                trace!("Replacing iCCP chunk with equivalent cICP chunk");
+                aux_chunks[iccp_idx] = cicp;
+            } else {
+                if let Some((_, cicp)) = cicp {
+                    trace!("Adding cICP chunk equivalent to iCCP chunk");
+                    aux_chunks.insert(iccp_idx, cicp);
+                    iccp_idx += 1;
+                }
+                let slimmed = if opts.slim_icc {
+                    slimmed_icc(&icc)
+                } else {
+                    None
+                };
+                if let Some(slimmed) = &slimmed {
                     debug!(
-                        "Recompressed iCCP chunk: {} ({} bytes decrease)",
-                        iccp.data.len(),
-                        cur_len - iccp.data.len()
+                        "Slimmed ICC profile: {} ({} bytes decrease)",
+                        slimmed.len(),
+                        icc.len() - slimmed.len()
                     );
-                    aux_chunks[iccp_idx] = iccp;
                 }
+                if opts.idat_recoding || slimmed.is_some() {
+                    // Try recompressing the profile
+                    let icc = slimmed.as_deref().unwrap_or(&icc);
+                    let cur_len = aux_chunks[iccp_idx].data.len();
+                    if let Ok(iccp) = make_iccp(icc, opts.deflater, Some(cur_len - 1)) {
+                        debug!(
+                            "Recompressed iCCP chunk: {} ({} bytes decrease)",
+                            iccp.data.len(),
+                            cur_len - iccp.data.len()
+                        );
+                        aux_chunks[iccp_idx] = iccp;
+                    }
+                }
+            }
+        }
+    }
+
+    let exif_stripped = sanitize_exif(aux_chunks, &opts.strip_exif);
+    if let Some(strip_xmp) = &opts.strip_xmp {
+        sanitize_xmp(
+            aux_chunks,
+            strip_xmp,
+            opts.deflater,
+            opts.max_decompressed_size,
+        );
+    }
+
+    if opts.idat_recoding {
+        recompress_text_chunks(aux_chunks, opts.deflater, opts.max_decompressed_size);
+    }
+
+    // Invalid HDR chunks would be misinterpreted by decoders, so they are removed
+    aux_chunks.retain(|c| {
+        let valid = valid_hdr_chunk(c);
+        if !valid {
+            warn!(
+                "Removing invalid {} chunk",
+                std::str::from_utf8(&c.name).unwrap()
+            );
+        }
+        valid
+    });
+    if let Some(cicp) = aux_chunks.iter().find(|c| &c.name == b"cICP") {
+        // The color primaries and transfer function apply to the RGB channels
+        allow_grayscale = false;
+        if HDR_TRANSFER_FUNCTIONS.contains(&cicp.data[1]) {
+            // Lossy reductions would damage HDR content, where small differences in code values
+            // can be significant
+            if opts.scale_16 {
+                warn!("Disabling 16-bit scaling due to HDR transfer function in cICP chunk");
+                opts.scale_16 = false;
+            }
+            #[cfg(feature = "lossy")]
+            if opts.quantize.is_some() {
+                warn!("Disabling quantization due to HDR transfer function in cICP chunk");
+                opts.quantize = None;
             }
         }
     }
 
     if !allow_grayscale && opts.grayscale_reduction {
-        debug!("Disabling grayscale reduction due to presence of sRGB or iCCP chunk");
+        debug!("Disabling grayscale reduction due to presence of sRGB, iCCP, cICP or cHRM chunk");
         opts.grayscale_reduction = false;
     }
 
     // Check for APNG by presence of acTL chunk
+    // Reductions are shared across all frames, but interlacing cannot be changed
     if aux_chunks.iter().any(|c| &c.name == b"acTL") {
-        warn!("APNG detected, disabling all reductions");
         opts.interlace = None;
-        opts.bit_depth_reduction = false;
-        opts.color_type_reduction = false;
-        opts.palette_reduction = false;
-    } else if let StripChunks::Keep(names) = &opts.strip {
+    }
+
+    if opts.strict_chunks {
+        // Unknown chunks that are not safe to copy must be kept intact, so the image data can't
+        // be changed
+        let mut unsafe_chunks = aux_chunks
+            .iter()
+            .filter(|c| c.is_unsafe_to_copy())
+            .peekable();
+        if unsafe_chunks.peek().is_some() {
+            for chunk in unsafe_chunks {
+                warn!(
+                    "{} chunk is not safe to copy, disabling all reductions",
+                    std::str::from_utf8(&chunk.name).unwrap()
+                );
+            }
+            opts.bit_depth_reduction = false;
+            opts.color_type_reduction = false;
+            opts.palette_reduction = false;
+            opts.grayscale_reduction = false;
+            opts.interlace = None;
+            opts.optimize_alpha = false;
+            opts.alpha_threshold = None;
+            opts.scale_16 = false;
+            #[cfg(feature = "lossy")]
+            {
+                opts.quantize = None;
+            }
+        }
+    }
+
+    if let StripChunks::Keep(names) = &opts.strip {
         // Check for explicitly kept chunks that will prevent reductions
         for name in Chunk::REDUCTION_CONFLICTS {
             if names.contains(&name) && aux_chunks.iter().any(|c| c.name == name) {
@@ -363,10 +754,13 @@ pub fn preprocess_chunks(aux_chunks: &mut Vec<Chunk>, opts: &mut Options) {
             }
         }
     }
+    exif_stripped
 }
 
 /// Perform cleanup of certain aux chunks after optimization has been completed
-pub fn postprocess_chunks(aux_chunks: &mut Vec<Chunk>, ihdr: &IhdrData, orig_ihdr: &IhdrData) {
+pub fn postprocess_chunks(aux_chunks: &mut Vec<Chunk>, image: &PngImage, orig_image: &PngImage) {
+    let ihdr = &image.ihdr;
+    let orig_ihdr = &orig_image.ihdr;
     // If the depth/color type has changed, some chunks may be invalid and should be dropped
     // While these could potentially be converted, they have no known use case today and are
     // generally more trouble than they're worth
@@ -396,4 +790,21 @@ pub fn postprocess_chunks(aux_chunks: &mut Vec<Chunk>, ihdr: &IhdrData, orig_ihd
             !invalid
         });
     }
+    // Unknown chunks that are not safe to copy may depend on the image data, so they must be
+    // dropped if it has changed. The PNG spec asks for them to be dropped whenever IDAT changes,
+    // but recompressing the same pixels can't affect them, and dropping them would make
+    // `strict_chunks` useless. The pixels only need comparing if reductions produced a new image
+    // with the same header.
+    if ihdr != orig_ihdr || !(std::ptr::eq(image, orig_image) || image.data == orig_image.data) {
+        aux_chunks.retain(|c| {
+            if c.is_unsafe_to_copy() {
+                warn!(
+                    "Removing {} chunk as it is not safe to copy after changes to the image data",
+                    std::str::from_utf8(&c.name).unwrap()
+                );
+                return false;
+            }
+            true
+        });
+    }
 }
@@ -0,0 +1,199 @@
+use crate::headers::{profile_tags, read_be_u32};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How to use a cICP chunk in place of a recognized ICC profile
+pub enum CicpMode {
+    /// Replace the iCCP chunk with a cICP chunk
+    Replace,
+    /// Add a cICP chunk, keeping the iCCP chunk as a fallback for decoders without cICP support
+    Add,
+}
+
+/// A color space that can be recognized from the colorants and tone curves of an ICC profile
+struct KnownProfile {
+    /// The D50-adapted red, green and blue colorants
+    colorants: [[f64; 3]; 3],
+    /// The tone curve for all channels
+    transfer: fn(f64) -> f64,
+    /// The cICP color primaries and transfer function
+    cicp: [u8; 2],
+}
+
+/// Color spaces which can be expressed by a cICP chunk. Adobe RGB has no code point in ITU-T H.273
+/// and so cannot be included.
+const KNOWN_PROFILES: [KnownProfile; 3] = [
+    // sRGB
+    KnownProfile {
+        colorants: [
+            [0.4360, 0.2225, 0.0139],
+            [0.3851, 0.7169, 0.0971],
+            [0.1430, 0.0606, 0.7139],
+        ],
+        transfer: srgb_transfer,
+        cicp: [1, 13],
+    },
+    // Display P3
+    KnownProfile {
+        colorants: [
+            [0.5151, 0.2412, -0.0011],
+            [0.2920, 0.6922, 0.0419],
+            [0.1571, 0.0666, 0.7841],
+        ],
+        transfer: srgb_transfer,
+        cicp: [12, 13],
+    },
+    // Rec. 2020
+    KnownProfile {
+        colorants: [
+            [0.6735, 0.2790, -0.0019],
+            [0.1657, 0.6753, 0.0300],
+            [0.1250, 0.0456, 0.7969],
+        ],
+        transfer: bt709_transfer,
+        cicp: [9, 1],
+    },
+];
+
+/// Maximum difference in each colorant component, allowing for rounding in different profiles
+const COLORANT_TOLERANCE: f64 = 0.003;
+/// Maximum difference in the tone curve output, allowing for tabulated curves
+const TRANSFER_TOLERANCE: f64 = 0.005;
+
+/// The sRGB transfer function (IEC 61966-2-1)
+fn srgb_transfer(x: f64) -> f64 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// The BT.709 transfer function, which is also used by BT.2020
+fn bt709_transfer(x: f64) -> f64 {
+    if x < 0.081 {
+        x / 4.5
+    } else {
+        ((x + 0.099) / 1.099).powf(1.0 / 0.45)
+    }
+}
+
+/// Read an ICC s15Fixed16Number
+fn read_s15f16(bytes: &[u8]) -> f64 {
+    f64::from(read_be_u32(bytes) as i32) / 65536.0
+}
+
+/// A tone curve from a curv or para tag
+enum Curve<'a> {
+    Gamma(f64),
+    Table(&'a [u8]),
+    Parametric(u16, [f64; 7]),
+}
+
+impl<'a> Curve<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        match data.get(0..4)? {
+            b"curv" => {
+                let count = read_be_u32(data.get(8..12)?) as usize;
+                let table = data.get(12..count.checked_mul(2)?.checked_add(12)?)?;
+                Some(match count {
+                    0 => Self::Gamma(1.0),
+                    1 => Self::Gamma(f64::from(u16::from_be_bytes([table[0], table[1]])) / 256.0),
+                    _ => Self::Table(table),
+                })
+            }
+            b"para" => {
+                let function = u16::from_be_bytes(data.get(8..10)?.try_into().unwrap());
+                let num_params = [1, 3, 4, 5, 7].get(function as usize)?;
+                let mut params = [0.0; 7];
+                for (i, param) in params.iter_mut().take(*num_params).enumerate() {
+                    *param = read_s15f16(data.get(12 + i * 4..16 + i * 4)?);
+                }
+                Some(Self::Parametric(function, params))
+            }
+            _ => None,
+        }
+    }
+
+    fn eval(&self, x: f64) -> f64 {
+        match *self {
+            Self::Gamma(g) => x.powf(g),
+            Self::Table(table) => {
+                let value =
+                    |i: usize| f64::from(u16::from_be_bytes([table[i * 2], table[i * 2 + 1]]));
+                let pos = x * (table.len() / 2 - 1) as f64;
+                let i = pos.floor() as usize;
+                let next = (i + 1).min(table.len() / 2 - 1);
+                let frac = pos - i as f64;
+                (value(i) * (1.0 - frac) + value(next) * frac) / 65535.0
+            }
+            Self::Parametric(function, [g, a, b, c, d, e, f]) => match function {
+                0 => x.powf(g),
+                1 if x >= -b / a => (a * x + b).powf(g),
+                1 => 0.0,
+                2 if x >= -b / a => (a * x + b).powf(g) + c,
+                2 => c,
+                3 if x >= d => (a * x + b).powf(g),
+                3 => c * x,
+                _ if x >= d => (a * x + b).powf(g) + e,
+                _ => c * x + f,
+            },
+        }
+    }
+}
+
+/// Get the equivalent cICP chunk data for an ICC profile, if it is a recognized color space
+pub fn cicp_for_icc(icc: &[u8]) -> Option<[u8; 4]> {
+    if icc.get(16..20)? != b"RGB " {
+        return None;
+    }
+    let tags = profile_tags(icc)?;
+    let tag = |sig: &[u8; 4]| tags.iter().find(|(s, _)| s == sig).map(|&(_, data)| data);
+
+    // Profiles for HDR transfer functions such as PQ and HLG can't be described by curves, but
+    // may include a cicp tag with the equivalent values
+    if let Some(cicp) = tag(b"cicp") {
+        let values = cicp.get(8..12).filter(|_| cicp.starts_with(b"cicp"))?;
+        // PNG only permits RGB matrix coefficients
+        return (values[2] == 0 && values[3] <= 1).then(|| values.try_into().unwrap());
+    }
+
+    // LUT-based transforms take precedence over the colorants and curves
+    if icc.get(20..24)? != b"XYZ "
+        || tags.iter().any(|(s, _)| {
+            [b"A2B", b"B2A", b"D2B", b"B2D"]
+                .iter()
+                .any(|p| s.starts_with(*p))
+        })
+    {
+        return None;
+    }
+    let mut colorants = [[0.0; 3]; 3];
+    for (colorant, sig) in colorants.iter_mut().zip([b"rXYZ", b"gXYZ", b"bXYZ"]) {
+        let data = tag(sig).filter(|d| d.starts_with(b"XYZ ") && d.len() >= 20)?;
+        for (i, value) in colorant.iter_mut().enumerate() {
+            *value = read_s15f16(&data[8 + i * 4..12 + i * 4]);
+        }
+    }
+    // All channels must share the same tone curve
+    let trc = tag(b"rTRC")?;
+    if tag(b"gTRC")? != trc || tag(b"bTRC")? != trc {
+        return None;
+    }
+    let curve = Curve::new(trc)?;
+
+    KNOWN_PROFILES
+        .iter()
+        .find(|known| {
+            known
+                .colorants
+                .iter()
+                .flatten()
+                .zip(colorants.iter().flatten())
+                .all(|(a, b)| (a - b).abs() <= COLORANT_TOLERANCE)
+                && (0..=32).all(|i| {
+                    let x = f64::from(i) / 32.0;
+                    (curve.eval(x) - (known.transfer)(x)).abs() <= TRANSFER_TOLERANCE
+                })
+        })
+        .map(|known| [known.cicp[0], known.cicp[1], 0, 1])
+}
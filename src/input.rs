@@ -0,0 +1,142 @@
+use std::{
+    fs,
+    io::{Read, stdin},
+    ops::Deref,
+    path::Path,
+};
+
+use log::warn;
+
+use crate::{InFile, PngError, PngResult};
+
+/// The bytes of an input file, either read into memory or mapped from the file
+pub enum InputData {
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(mapped::MappedFile),
+}
+
+impl InputData {
+    /// Read the input, mapping it into memory if requested and the input is a file
+    pub fn read(input: &InFile, memory_map: bool) -> PngResult<Self> {
+        match *input {
+            InFile::Path(ref input_path) => Self::read_file(input_path, memory_map),
+            InFile::StdIn => {
+                let mut data = Vec::new();
+                stdin()
+                    .read_to_end(&mut data)
+                    .map_err(|e| PngError::ReadFailed("stdin".into(), e))?;
+                Ok(Self::Owned(data))
+            }
+        }
+    }
+
+    /// Read a file, mapping it into memory if requested. Falls back to reading the file if it
+    /// can't be mapped.
+    pub fn read_file(path: &Path, memory_map: bool) -> PngResult<Self> {
+        #[cfg(feature = "mmap")]
+        if memory_map {
+            match mapped::MappedFile::open(path) {
+                Ok(Some(file)) => return Ok(Self::Mapped(file)),
+                Ok(None) => (),
+                Err(e) => warn!("Unable to map {}, reading instead: {e}", path.display()),
+            }
+        }
+        #[cfg(not(feature = "mmap"))]
+        if memory_map {
+            warn!("Memory mapping is not supported in this build, reading instead");
+        }
+        fs::read(path)
+            .map(Self::Owned)
+            .map_err(|e| PngError::ReadFailed(path.display().to_string(), e))
+    }
+
+    /// If the data is mapped from a file that has been modified since it was mapped, read the
+    /// current contents of the file into memory instead. Returns whether the data was replaced,
+    /// in which case anything derived from the previous data is no longer valid.
+    #[cfg_attr(not(feature = "mmap"), allow(clippy::missing_const_for_fn))]
+    pub fn reload_if_modified(&mut self, path: &Path) -> PngResult<bool> {
+        #[cfg(feature = "mmap")]
+        if let Self::Mapped(file) = self {
+            if file.is_modified() {
+                warn!(
+                    "{} was modified while being read, reading it again",
+                    path.display()
+                );
+                *self = Self::read_file(path, false)?;
+                return Ok(true);
+            }
+        }
+        #[cfg(not(feature = "mmap"))]
+        let _ = path;
+        Ok(false)
+    }
+}
+
+impl Deref for InputData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Owned(data) => data,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(file) => file,
+        }
+    }
+}
+
+#[cfg(feature = "mmap")]
+mod mapped {
+    use std::{fs::File, io, ops::Deref, path::Path, time::SystemTime};
+
+    use memmap2::Mmap;
+
+    /// A read-only mapping of a file, along with what is needed to tell whether the file has been
+    /// modified since it was mapped
+    pub struct MappedFile {
+        map: Mmap,
+        file: File,
+        len: u64,
+        modified: Option<SystemTime>,
+    }
+
+    impl MappedFile {
+        /// Map a file into memory. Returns `None` for files that can't usefully be mapped, such as
+        /// empty files and pipes.
+        pub fn open(path: &Path) -> io::Result<Option<Self>> {
+            let file = File::open(path)?;
+            let meta = file.metadata()?;
+            if !meta.is_file() || meta.len() == 0 {
+                return Ok(None);
+            }
+            // SAFETY: Another process writing to the file while it is mapped is undefined
+            // behavior, and truncating it causes SIGBUS when the removed pages are read. Neither
+            // can be prevented here, so mapping is opt-in and requires that the file is left
+            // alone while it is being optimized. Checking `is_modified` afterwards only avoids
+            // writing a result from detectably changed data; it does not make such changes sound.
+            let map = unsafe { Mmap::map(&file)? };
+            Ok(Some(Self {
+                map,
+                len: meta.len(),
+                modified: meta.modified().ok(),
+                file,
+            }))
+        }
+
+        /// Whether the file has been written to or resized since it was mapped
+        pub fn is_modified(&self) -> bool {
+            !self
+                .file
+                .metadata()
+                .is_ok_and(|meta| meta.len() == self.len && meta.modified().ok() == self.modified)
+        }
+    }
+
+    impl Deref for MappedFile {
+        type Target = [u8];
+
+        fn deref(&self) -> &[u8] {
+            &self.map
+        }
+    }
+}
@@ -7,8 +7,8 @@ extern crate rayon;
 mod rayon;
 
 use std::{
-    fs::File,
-    io::{BufWriter, Read, Write, stdin, stdout},
+    fs::{self, File},
+    io::{BufWriter, Write, stdout},
     path::PathBuf,
     sync::{
         Arc,
@@ -20,47 +20,69 @@ use std::{
 pub use indexmap::{IndexSet, indexset};
 use log::{debug, info, trace, warn};
 use rayon::prelude::*;
-pub use rgb::{RGB16, RGBA8};
+pub use rgb::{RGB16, RGBA8, RGBA16};
 
 #[cfg(feature = "zopfli")]
 pub use crate::deflate::ZopfliOptions;
+#[cfg(feature = "lossy")]
+pub use crate::reduction::quantize::QuantizeOptions;
+use crate::{
+    apng::{composited_apng, merge_duplicate_frames, optimized_apng},
+    cgbi::is_cgbi,
+    evaluate::{Candidate, Evaluator},
+    headers::*,
+    input::InputData,
+    png::{PngData, PngImage},
+    reduction::*,
+    stream::optimize_streaming,
+    text::apply_text_edits,
+    verify::verify_output,
+};
 pub use crate::{
     colors::{BitDepth, ColorType},
     deflate::Deflater,
     error::PngError,
+    exif::ExifStrip,
     filters::{FilterStrategy, RowFilter},
     headers::StripChunks,
+    icc::CicpMode,
     options::{InFile, Options, OutFile},
-};
-use crate::{
-    evaluate::{Candidate, Evaluator},
-    headers::*,
-    png::{PngData, PngImage},
-    reduction::*,
+    recovery::RecoveryMode,
+    text::TextChunk,
+    xmp::XmpStrip,
 };
 
 mod apng;
 mod atomicmin;
+mod cgbi;
 mod colors;
 mod deflate;
 mod display_chunks;
 mod error;
 mod evaluate;
+mod exif;
 mod filters;
 mod headers;
+mod icc;
+mod input;
 mod interlace;
 mod options;
 mod png;
+mod recovery;
 mod reduction;
 #[cfg(feature = "sanity-checks")]
 mod sanity_checks;
+mod stream;
+mod text;
+mod verify;
+mod xmp;
 
 /// Private to oxipng; don't use outside tests and benches
 #[doc(hidden)]
 pub mod internal_tests {
     #[cfg(feature = "sanity-checks")]
     pub use crate::sanity_checks::*;
-    pub use crate::{deflate::*, png::*, reduction::*};
+    pub use crate::{apng::Frame, deflate::*, headers::*, png::*, reduction::*, verify::*};
 }
 
 pub type PngResult<T> = Result<T, PngError>;
@@ -126,6 +148,14 @@ impl RawImage {
         self.aux_chunks.push(Chunk { name, data });
     }
 
+    /// Add a text chunk to be included in the output
+    pub fn add_text_chunk(&mut self, text: &TextChunk) -> PngResult<()> {
+        // Compress with fastest compression level - will be recompressed during optimization
+        let deflater = Deflater::Libdeflater { compression: 1 };
+        self.aux_chunks.push(text.to_chunk(deflater)?);
+        Ok(())
+    }
+
     /// Add an ICC profile for the image
     pub fn add_icc_profile(&mut self, data: &[u8]) {
         // Compress with fastest compression level - will be recompressed during optimization
@@ -144,6 +174,8 @@ impl RawImage {
             .filter(|c| opts.strip.keep(&c.name))
             .cloned()
             .collect();
+        normalize_chunk_order(&mut aux_chunks, None);
+        apply_text_edits(&mut aux_chunks, &opts)?;
         preprocess_chunks(&mut aux_chunks, &mut opts);
 
         let deadline = Arc::new(Deadline::new(opts.timeout));
@@ -156,8 +188,9 @@ impl RawImage {
             idat_data: result.idat_data.unwrap(),
             aux_chunks,
             frames: Vec::new(),
+            recovered: false,
         };
-        postprocess_chunks(&mut png.aux_chunks, &png.raw.ihdr, &self.png.ihdr);
+        postprocess_chunks(&mut png.aux_chunks, &png.raw, &self.png);
 
         Ok(png.output())
     }
@@ -172,39 +205,50 @@ pub fn optimize(input: &InFile, output: &OutFile, opts: &Options) -> Optimizatio
 
     let deadline = Arc::new(Deadline::new(opts.timeout));
 
-    let in_data = match *input {
-        InFile::Path(ref input_path) => PngData::read_file(input_path)?,
-        InFile::StdIn => {
-            let mut data = Vec::new();
-            stdin()
-                .read_to_end(&mut data)
-                .map_err(|e| PngError::ReadFailed("stdin".into(), e))?;
-            data
-        }
-    };
+    let mut in_data = InputData::read(input, opts.memory_map)?;
 
-    let mut png = PngData::from_slice(&in_data, opts)?;
+    let mut result = optimize_data(&in_data, opts, deadline.clone());
+    if let InFile::Path(input_path) = input {
+        // If a mapped file was modified while being read, the result can't be trusted
+        if in_data.reload_if_modified(input_path)? {
+            result = optimize_data(&in_data, opts, deadline);
+        }
+    }
 
-    // Run the optimizer on the decoded PNG.
-    let mut optimized_output = optimize_png(&mut png, &in_data, opts, deadline)?;
+    // Fall back to the original if verification fails
+    let (optimized_output, must_keep, verify_error) = match result {
+        Err(e @ PngError::VerificationFailed(..)) => (Vec::new(), false, Some(e)),
+        result => {
+            let (output, must_keep) = result?;
+            (output, must_keep, None)
+        }
+    };
 
     let in_length = in_data.len();
 
-    if is_fully_optimized(in_length, optimized_output.len(), opts) {
+    let keep_original = verify_error.is_some()
+        || is_fully_optimized(must_keep, &in_data, optimized_output.len(), opts);
+    if keep_original {
         match (output, input) {
             // If output path is None, it also means same as the input path
             (OutFile::Path { path, .. }, InFile::Path(input_path))
                 if path.as_ref().is_none_or(|p| p == input_path) =>
             {
+                if let Some(e) = verify_error {
+                    return Err(e);
+                }
                 info!("Could not optimize further, no change written: {input}");
                 return Ok((in_length, in_length));
             }
-            _ => {
-                optimized_output = in_data;
-            }
+            _ => (),
         }
     }
 
+    // Release the original unless it is being copied to the output, as a mapped file may not be
+    // overwritten while the mapping is open on some platforms
+    let original = keep_original.then_some(in_data);
+    let optimized_output = original.as_deref().unwrap_or(&optimized_output);
+
     let savings = if in_length >= optimized_output.len() {
         format!(
             "{} bytes ({:.2}% smaller)",
@@ -226,7 +270,7 @@ pub fn optimize(input: &InFile, output: &OutFile, opts: &Options) -> Optimizatio
         (&OutFile::StdOut, _) | (&OutFile::Path { path: None, .. }, &InFile::StdIn) => {
             let mut buffer = BufWriter::new(stdout());
             buffer
-                .write_all(&optimized_output)
+                .write_all(optimized_output)
                 .map_err(|e| PngError::WriteFailed("stdout".into(), e))?;
             info!("{savings}: stdout");
         }
@@ -257,7 +301,7 @@ pub fn optimize(input: &InFile, output: &OutFile, opts: &Options) -> Optimizatio
 
             let mut buffer = BufWriter::new(&out_file);
             buffer
-                .write_all(&optimized_output)
+                .write_all(optimized_output)
                 // flush BufWriter so IO errors don't get swallowed silently on close() by drop!
                 .and_then(|()| buffer.flush())
                 .map_err(|e| PngError::WriteFailed(output_path.display().to_string(), e))?;
@@ -279,7 +323,121 @@ pub fn optimize(input: &InFile, output: &OutFile, opts: &Options) -> Optimizatio
             info!("{}: {}", savings, output_path.display());
         }
     }
-    Ok((in_length, optimized_output.len()))
+    verify_error.map_or(Ok((in_length, optimized_output.len())), Err)
+}
+
+/// Composite the frames of an APNG and write them out as standalone optimized PNGs, using the
+/// options provided
+///
+/// If `frame` is given, only that frame is exported, where 0 is the first frame of the animation.
+/// Otherwise all frames are exported. Each frame is written to the output path with the frame
+/// number appended to the file name, unless a single frame is exported to an explicit path.
+///
+/// Returns the original file size and the total size of the exported frames
+pub fn export_frames(
+    input: &InFile,
+    output: &OutFile,
+    frame: Option<usize>,
+    opts: &Options,
+) -> OptimizationResult {
+    info!("Exporting frames: {input}");
+
+    let in_data = InputData::read(input, opts.memory_map)?;
+    let frames = export_frames_from_memory(&in_data, frame, opts)?;
+    let out_size = frames.iter().map(Vec::len).sum();
+
+    match output {
+        OutFile::None => {
+            info!("{} frames exported: Dry run, no output", frames.len());
+        }
+        OutFile::StdOut => {
+            let [data] = frames.as_slice() else {
+                return Err(PngError::new(
+                    "Only a single frame can be exported to stdout",
+                ));
+            };
+            let mut buffer = BufWriter::new(stdout());
+            buffer
+                .write_all(data)
+                .and_then(|()| buffer.flush())
+                .map_err(|e| PngError::WriteFailed("stdout".into(), e))?;
+        }
+        OutFile::Path { path, .. } => {
+            let base = path
+                .as_deref()
+                .or_else(|| input.path())
+                .ok_or_else(|| PngError::new("No output path for exported frames"))?;
+            let first = frame.unwrap_or(0);
+            for (i, data) in frames.iter().enumerate() {
+                let output_path = if frame.is_some() && path.is_some() {
+                    base.to_path_buf()
+                } else {
+                    let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+                    base.with_file_name(format!("{stem}.frame{}.png", first + i))
+                };
+                fs::write(&output_path, data)
+                    .map_err(|e| PngError::WriteFailed(output_path.display().to_string(), e))?;
+                info!("{} bytes: {}", data.len(), output_path.display());
+            }
+        }
+    }
+    Ok((in_data.len(), out_size))
+}
+
+/// Composite the frames of an APNG and create standalone optimized PNGs from them, where the file
+/// is already loaded in-memory
+///
+/// If `frame` is given, only that frame is exported, where 0 is the first frame of the animation.
+/// Otherwise all frames are exported in order. A static PNG is treated as having a single frame.
+pub fn export_frames_from_memory(
+    data: &[u8],
+    frame: Option<usize>,
+    opts: &Options,
+) -> PngResult<Vec<Vec<u8>>> {
+    // Read without stripping any chunks, so the animation is retained
+    let read_opts = Options {
+        strip: StripChunks::None,
+        ..opts.clone()
+    };
+    let png = PngData::from_slice(data, &read_opts)?;
+    let mut canvases = composited_apng(&png)?;
+    if let Some(i) = frame {
+        if i >= canvases.len() {
+            return Err(PngError::new(&format!(
+                "Frame {i} does not exist, the image has {} frames",
+                canvases.len()
+            )));
+        }
+        canvases = vec![canvases.swap_remove(i)];
+    }
+
+    // Carry over the metadata, except for chunks that depend on the original color type
+    let ihdr = &png.raw.ihdr;
+    let is_rgba = ihdr.color_type == ColorType::RGBA && ihdr.bit_depth == BitDepth::Eight;
+    let aux_chunks: Vec<&Chunk> = png
+        .aux_chunks
+        .iter()
+        .filter(|c| !matches!(&c.name, b"IDAT" | b"acTL" | b"fcTL"))
+        .filter(|c| is_rgba || !Chunk::REDUCTION_CONFLICTS.contains(&c.name))
+        .collect();
+
+    canvases
+        .par_iter()
+        .map(|pixels| {
+            let data = pixels.iter().flat_map(|px| [px.r, px.g, px.b, px.a]);
+            let mut raw = RawImage::new(
+                ihdr.width,
+                ihdr.height,
+                ColorType::RGBA,
+                BitDepth::Eight,
+                data.collect(),
+            )?;
+            for chunk in &aux_chunks {
+                raw.add_png_chunk(chunk.name, chunk.data.clone());
+            }
+            raw.create_optimized_png(opts)
+        })
+        .collect()
 }
 
 /// Perform optimization on the input file using the options provided, where the file is already
@@ -290,13 +448,9 @@ pub fn optimize_from_memory(data: &[u8], opts: &Options) -> PngResult<Vec<u8>> {
 
     let deadline = Arc::new(Deadline::new(opts.timeout));
 
-    let original_size = data.len();
-    let mut png = PngData::from_slice(data, opts)?;
-
-    // Run the optimizer on the decoded PNG.
-    let optimized_output = optimize_png(&mut png, data, opts, deadline)?;
+    let (optimized_output, must_keep) = optimize_data(data, opts, deadline)?;
 
-    if is_fully_optimized(original_size, optimized_output.len(), opts) {
+    if is_fully_optimized(must_keep, data, optimized_output.len(), opts) {
         info!("Image already optimized");
         Ok(data.to_vec())
     } else {
@@ -304,13 +458,30 @@ pub fn optimize_from_memory(data: &[u8], opts: &Options) -> PngResult<Vec<u8>> {
     }
 }
 
-/// Perform optimization on the input PNG object using the options provided
+/// Decode and optimize the PNG data, returning the output and whether it must be kept even if it is
+/// not smaller, as when the input was repaired or EXIF data was stripped. Very large images may be
+/// streamed rather than decoded in full.
+fn optimize_data(
+    data: &[u8],
+    opts: &Options,
+    deadline: Arc<Deadline>,
+) -> PngResult<(Vec<u8>, bool)> {
+    if let Some(result) = optimize_streaming(data, opts, &deadline)? {
+        return Ok(result);
+    }
+    let mut png = PngData::from_slice(data, opts)?;
+    let (output, exif_stripped) = optimize_png(&mut png, data, opts, deadline)?;
+    Ok((output, png.recovered || exif_stripped))
+}
+
+/// Perform optimization on the input PNG object using the options provided, returning the output
+/// and whether any EXIF data was stripped
 fn optimize_png(
     png: &mut PngData,
     original_data: &[u8],
     opts: &Options,
     deadline: Arc<Deadline>,
-) -> PngResult<Vec<u8>> {
+) -> PngResult<(Vec<u8>, bool)> {
     // Print png info
     let file_original_size = original_data.len();
     let idat_original_size = png.idat_data.len();
@@ -323,19 +494,87 @@ fn optimize_png(
     debug!("    IDAT size = {idat_original_size} bytes");
     debug!("    File size = {file_original_size} bytes");
 
+    let original = (opts.verify && !opts.is_lossy()).then(|| png.clone());
     let mut opts = opts.to_owned();
-    preprocess_chunks(&mut png.aux_chunks, &mut opts);
+    apply_text_edits(&mut png.aux_chunks, &opts)?;
+    let exif_stripped = preprocess_chunks(&mut png.aux_chunks, &mut opts);
+
+    // Merging frames changes the frame count, in which case the output can't be validated
+    #[cfg(feature = "sanity-checks")]
+    let original_frames = png.frames.len();
+    if opts.merge_frames && !png.frames.is_empty() {
+        let removed = merge_duplicate_frames(png)?;
+        if removed > 0 {
+            debug!("Merged {removed} duplicate frames");
+        }
+    }
 
-    let max_size = if opts.force {
+    let mut max_size = if opts.force {
         None
     } else {
         Some(png.raw.estimated_output_size(&png.idat_data))
     };
-    if let Some(result) = optimize_raw(raw.clone(), &opts, deadline.clone(), max_size) {
+    let mut image = raw.clone();
+    let mut new_frames = None;
+    if !png.frames.is_empty() {
+        // The frames of an APNG must all be reduced together
+        let reduce = opts.bit_depth_reduction
+            || opts.color_type_reduction
+            || opts.palette_reduction
+            || opts.alpha_threshold.is_some();
+        if reduce || opts.optimize_frames {
+            let mut frames = png.frames.clone();
+            let mut images = Vec::with_capacity(frames.len() + 1);
+            images.push(PngImage::clone(&raw));
+            for frame in &frames {
+                images.push(frame.image(&raw.ihdr)?);
+            }
+            let mut changed = false;
+            if opts.optimize_frames {
+                if let Some((optimized, rgba)) = optimized_apng(png, &images, &deadline)? {
+                    debug!("Optimized {} frames", optimized.len());
+                    frames = optimized;
+                    images = rgba;
+                    changed = true;
+                }
+            }
+            if reduce {
+                if let Some(reduced) = perform_apng_reductions(&images, &opts, &deadline) {
+                    images = reduced;
+                    changed = true;
+                }
+            }
+            if changed {
+                report_format("Transformed all frames to ", &images[0]);
+                image = Arc::new(images.remove(0));
+                new_frames = Some((frames, images));
+                // The frames will need to be recompressed, and the main image must be accepted even
+                // if it is larger, as it can no longer be paired with the original frames
+                opts.idat_recoding = true;
+                max_size = None;
+            }
+        }
+        // Prevent any further reductions which would apply only to the main image
+        opts.bit_depth_reduction = false;
+        opts.color_type_reduction = false;
+        opts.palette_reduction = false;
+        opts.alpha_threshold = None;
+        #[cfg(feature = "lossy")]
+        {
+            opts.quantize = None;
+        }
+    }
+    if let Some(result) = optimize_raw(image, &opts, deadline.clone(), max_size) {
         png.raw = result.image;
         png.idat_data = result.idat_data.unwrap();
-        recompress_frames(png, &opts, deadline, result.filter)?;
-        postprocess_chunks(&mut png.aux_chunks, &png.raw.ihdr, &raw.ihdr);
+        if let Some((mut frames, images)) = new_frames {
+            for (frame, image) in frames.iter_mut().zip(images) {
+                frame.data = encode_frame(&image)?;
+            }
+            png.frames = frames;
+        }
+        recompress_frames(png, &opts, deadline)?;
+        postprocess_chunks(&mut png.aux_chunks, &png.raw, &raw);
     }
 
     let output = png.output();
@@ -376,9 +615,18 @@ fn optimize_png(
     }
 
     #[cfg(feature = "sanity-checks")]
-    assert!(sanity_checks::validate_output(&output, original_data));
+    assert!(
+        opts.is_lossy()
+            || png.frames.len() != original_frames
+            || sanity_checks::validate_output(&output, original_data)
+    );
+
+    if let Some(original) = &original {
+        verify_output(&output, original, opts.merge_frames)?;
+        debug!("Verified output matches the input");
+    }
 
-    Ok(output)
+    Ok((output, exif_stripped))
 }
 
 /// Perform optimization on the input image data using the options provided
@@ -388,19 +636,7 @@ fn optimize_raw(
     deadline: Arc<Deadline>,
     max_size: Option<usize>,
 ) -> Option<Candidate> {
-    // Libdeflate has four algorithms: 0 = 'uncompressed', 1-4 = 'greedy', 5-7 = 'lazy', 8-9 = 'lazy2', 10-12 = 'near-optimal'
-    // 5 is the minimumm required for a decent evaluation result
-    // 7 is not noticeably slower than 5 and improves evaluation of filters in 'fast' mode (o2 and lower)
-    // 8 is a little slower but not noticeably when used only for reductions (o3 and higher)
-    // 9 is not appreciably better than 8
-    // 10 and higher are quite slow - good for filters but only good for reductions if matching the main zc level
-    let compression = match opts.deflater {
-        Deflater::Libdeflater { compression } => {
-            if opts.fast_evaluation { 7 } else { 8 }.min(compression)
-        }
-        _ => 8,
-    };
-    let eval_deflater = Deflater::Libdeflater { compression };
+    let eval_deflater = evaluation_deflater(opts);
     // If only one filter is selected, use this for evaluations
     let eval_filters = if opts.filters.len() == 1 {
         opts.filters.clone()
@@ -456,6 +692,24 @@ fn optimize_raw(
     None
 }
 
+/// Determine the deflater to use for evaluations
+const fn evaluation_deflater(opts: &Options) -> Deflater {
+    // Libdeflate has four algorithms: 0 = 'uncompressed', 1-4 = 'greedy', 5-7 = 'lazy', 8-9 = 'lazy2', 10-12 = 'near-optimal'
+    // 5 is the minimumm required for a decent evaluation result
+    // 7 is not noticeably slower than 5 and improves evaluation of filters in 'fast' mode (o2 and lower)
+    // 8 is a little slower but not noticeably when used only for reductions (o3 and higher)
+    // 9 is not appreciably better than 8
+    // 10 and higher are quite slow - good for filters but only good for reductions if matching the main zc level
+    let compression = match opts.deflater {
+        Deflater::Libdeflater { compression } => {
+            let max = if opts.fast_evaluation { 7 } else { 8 };
+            if compression < max { compression } else { max }
+        }
+        _ => 8,
+    };
+    Deflater::Libdeflater { compression }
+}
+
 /// Perform compression trials
 fn perform_trials(
     image: Arc<PngImage>,
@@ -601,18 +855,12 @@ fn report_format(prefix: &str, png: &PngImage) {
     );
 }
 
-/// Recompress the additional frames of an APNG
-fn recompress_frames(
-    png: &mut PngData,
-    opts: &Options,
-    deadline: Arc<Deadline>,
-    filter: FilterStrategy,
-) -> PngResult<()> {
+/// Recompress the additional frames of an APNG, evaluating the filters separately for each frame
+fn recompress_frames(png: &mut PngData, opts: &Options, deadline: Arc<Deadline>) -> PngResult<()> {
     if !opts.idat_recoding || png.frames.is_empty() {
         return Ok(());
     }
-    // Ensure we don't try to recompress frames with a predefined filter
-    debug_assert!(!matches!(filter, FilterStrategy::Predefined { .. }));
+    let eval_deflater = evaluation_deflater(opts);
     png.frames
         .par_iter_mut()
         .with_max_len(1)
@@ -621,26 +869,58 @@ fn recompress_frames(
             if deadline.passed() {
                 return Ok(());
             }
-            let mut ihdr = png.raw.ihdr.clone();
-            ihdr.width = frame.width;
-            ihdr.height = frame.height;
-            let image = PngImage::new(ihdr, &frame.data)?;
-            let (filtered, _) = image.filter_image(filter.clone(), opts.optimize_alpha);
-            let max_size = Some(frame.data.len() - 1);
-            if let Ok(data) = opts.deflater.deflate(&filtered, max_size) {
-                debug!(
-                    "Recompressed fdAT #{:<2}: {} ({} bytes decrease)",
-                    i,
-                    data.len(),
-                    frame.data.len() - data.len()
-                );
-                frame.data = data;
+            let image = Arc::new(frame.image(&png.raw.ihdr)?);
+            let max_size = image.estimated_output_size(&frame.data);
+            let result = perform_trials(
+                image,
+                opts,
+                deadline.clone(),
+                Some(max_size),
+                None,
+                IndexSet::new(),
+                eval_deflater,
+            );
+            if let Some(Candidate {
+                idat_data: Some(data),
+                estimated_output_size,
+                filter,
+                ..
+            }) = result
+            {
+                if estimated_output_size < max_size {
+                    debug!(
+                        "Recompressed fdAT #{:<2}: {} ({} bytes decrease), f = {}",
+                        i,
+                        data.len(),
+                        frame.data.len().saturating_sub(data.len()),
+                        filter
+                    );
+                    frame.data = data;
+                }
             }
             Ok(())
         })
 }
 
+/// Quickly compress a reduced APNG frame so that it can be properly recompressed afterwards
+fn encode_frame(image: &PngImage) -> PngResult<Vec<u8>> {
+    let (filtered, _) = image.filter_image(FilterStrategy::NONE, false);
+    Deflater::Libdeflater { compression: 1 }.deflate(&filtered, None)
+}
+
 /// Check if an image was already optimized prior to oxipng's operations
-const fn is_fully_optimized(original_size: usize, optimized_size: usize, opts: &Options) -> bool {
-    original_size <= optimized_size && !opts.force
+fn is_fully_optimized(
+    must_keep: bool,
+    original_data: &[u8],
+    optimized_size: usize,
+    opts: &Options,
+) -> bool {
+    // Metadata edits, converted CgBI images, repaired images and stripped EXIF data must always be
+    // written
+    original_data.len() <= optimized_size
+        && !opts.force
+        && opts.set_text.is_empty()
+        && opts.remove_text.is_empty()
+        && !is_cgbi(original_data)
+        && !must_keep
 }
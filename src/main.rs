@@ -15,12 +15,16 @@ use std::{
 
 use clap::ArgMatches;
 mod cli;
+mod profile;
 use indexmap::IndexSet;
 use log::{Level, LevelFilter, error, warn};
+#[cfg(feature = "lossy")]
+use oxipng::QuantizeOptions;
 #[cfg(feature = "zopfli")]
 use oxipng::ZopfliOptions;
 use oxipng::{
-    Deflater, FilterStrategy, InFile, OptimizationResult, Options, OutFile, PngError, StripChunks,
+    CicpMode, Deflater, ExifStrip, FilterStrategy, InFile, OptimizationResult, Options, OutFile,
+    PngError, RecoveryMode, StripChunks, TextChunk, XmpStrip,
 };
 use rayon::prelude::*;
 
@@ -93,8 +97,12 @@ fn main() -> ExitCode {
         print!("Files processed: 0/{}...", total_files);
         stdout().flush().ok();
     }
+    // When exporting frames, the inner value is the frame to export or None for all frames
+    let export_frames = matches
+        .contains_id("export-frames")
+        .then(|| matches.get_one::<usize>("export-frames").copied());
     let process = |(input, output): &(InFile, OutFile)| {
-        let result = process_file(input, output, &opts);
+        let result = process_file(input, output, export_frames, &opts);
         if print_progress && matches!(result, OptimizationResult::Ok(_)) {
             let value = num_processed.fetch_add(1, AcqRel) + 1;
             print!("\rFiles processed: {}/{}...", value, total_files);
@@ -340,8 +348,31 @@ fn parse_opts_into_struct(
 
     opts.optimize_alpha = matches.get_flag("alpha");
 
+    opts.alpha_threshold = matches
+        .get_one::<i64>("alpha-threshold")
+        .map(|&x| x as u8)
+        .filter(|&x| x > 0);
+
+    opts.optimize_frames = matches.get_flag("optimize-frames");
+
+    opts.merge_frames = matches.get_flag("merge-frames");
+
     opts.scale_16 = matches.get_flag("scale16");
 
+    if let Some(&quality) = matches.get_one::<i64>("lossy") {
+        #[cfg(feature = "lossy")]
+        {
+            opts.quantize = Some(QuantizeOptions {
+                min_quality: quality as u8,
+                dither: matches.get_flag("dither"),
+            });
+        }
+        #[cfg(not(feature = "lossy"))]
+        return Err(format!(
+            "--lossy {quality} requires oxipng to be built with the 'lossy' feature"
+        ));
+    }
+
     // The default value for fast depends on the preset - make sure we don't change when not provided
     if matches.get_flag("fast") {
         opts.fast_evaluation = matches.get_flag("fast");
@@ -350,8 +381,18 @@ fn parse_opts_into_struct(
     opts.force = matches.get_flag("force");
 
     opts.fix_errors = matches.get_flag("fix");
+    opts.recover = matches
+        .get_one::<String>("recover")
+        .map(|mode| match mode.as_str() {
+            "crop" => RecoveryMode::Crop,
+            _ => RecoveryMode::Pad,
+        });
+    opts.strict_cgbi = matches.get_flag("strict-cgbi");
+    opts.verify = matches.get_flag("verify");
 
     opts.max_decompressed_size = matches.get_one::<u64>("max-size").map(|&x| x as usize);
+    opts.stream_decompressed_size = matches.get_one::<u64>("stream-size").map(|&x| x as usize);
+    opts.memory_map = matches.get_flag("mmap");
 
     opts.bit_depth_reduction = !matches.get_flag("no-bit-reduction");
 
@@ -371,6 +412,8 @@ fn parse_opts_into_struct(
 
     opts.idat_recoding = !matches.get_flag("no-recoding");
 
+    opts.strict_chunks = matches.get_flag("strict-chunks");
+
     if let Some(x) = matches.get_one::<String>("interlace") {
         opts.interlace = match x.as_str() {
             "off" | "0" => Some(false),
@@ -379,28 +422,8 @@ fn parse_opts_into_struct(
         };
     }
 
-    const FORBIDDEN_CHUNKS: [[u8; 4]; 5] = [*b"IHDR", *b"IDAT", *b"tRNS", *b"PLTE", *b"IEND"];
     if let Some(keep) = matches.get_one::<String>("keep") {
-        let mut keep_display = false;
-        let mut names = keep
-            .split(',')
-            .filter_map(|x| {
-                if x == "display" {
-                    keep_display = true;
-                    return None;
-                }
-                Some(match parse_chunk_name(x) {
-                    Ok(name) if FORBIDDEN_CHUNKS.contains(&name) => Err(format!(
-                        "{x} chunk is controlled internally and may not be explicitly kept"
-                    )),
-                    name => name,
-                })
-            })
-            .collect::<Result<IndexSet<_>, _>>()?;
-        if keep_display {
-            names.extend(DISPLAY_CHUNKS.iter().copied());
-        }
-        opts.strip = StripChunks::Keep(names);
+        opts.strip = StripChunks::Keep(parse_keep_list(keep)?);
     }
 
     if let Some(strip) = matches.get_one::<String>("strip") {
@@ -409,23 +432,7 @@ fn parse_opts_into_struct(
         } else if strip == "all" {
             opts.strip = StripChunks::All;
         } else {
-            let names = strip
-                .split(',')
-                .map(|x| {
-                    if x == "safe" || x == "all" {
-                        return Err(
-                            "'safe' or 'all' presets for --strip should be used by themselves"
-                                .to_owned(),
-                        );
-                    }
-                    let name = parse_chunk_name(x)?;
-                    if FORBIDDEN_CHUNKS.contains(&name) {
-                        return Err(format!("{x} chunk is not allowed to be stripped"));
-                    }
-                    Ok(name)
-                })
-                .collect::<Result<_, _>>()?;
-            opts.strip = StripChunks::Strip(names);
+            opts.strip = StripChunks::Strip(parse_strip_list(strip)?);
         }
     }
 
@@ -433,6 +440,64 @@ fn parse_opts_into_struct(
         opts.strip = StripChunks::Safe;
     }
 
+    if let Some(name) = matches.get_one::<String>("strip-profile") {
+        let mut profiles = profile::builtin_profiles();
+        if let Some(path) = matches.get_one::<PathBuf>("strip-config") {
+            profiles.extend(profile::load_profiles(path)?);
+        }
+        let profile = profiles
+            .get(name)
+            .ok_or_else(|| format!("Unknown strip profile {name}"))?;
+        profile.apply(&mut opts);
+    }
+
+    if let Some(strip_exif) = matches.get_one::<String>("strip-exif") {
+        opts.strip_exif = parse_exif_list(strip_exif)?;
+    }
+
+    if matches.contains_id("strip-xmp") {
+        opts.strip_xmp = Some(parse_xmp_lists(
+            matches.get_one::<String>("strip-xmp").map(String::as_str),
+            matches.get_one::<String>("keep-xmp").map(String::as_str),
+        ));
+    }
+
+    if let Some(texts) = matches.get_many::<String>("set-text") {
+        opts.set_text = texts
+            .map(|x| {
+                let (keyword, value) = x
+                    .split_once('=')
+                    .ok_or_else(|| format!("Invalid text {x}, expected keyword=value"))?;
+                let text = TextChunk::new(keyword, value);
+                text.validate().map_err(|e| e.to_string())?;
+                Ok(text)
+            })
+            .collect::<Result<_, String>>()?;
+    }
+
+    if let Some(keywords) = matches.get_many::<String>("remove-text") {
+        opts.remove_text = keywords
+            .map(|x| {
+                if !TextChunk::is_valid_keyword(x) {
+                    return Err(PngError::InvalidKeyword(x.clone()).to_string());
+                }
+                Ok(x.clone())
+            })
+            .collect::<Result<_, _>>()?;
+    }
+
+    if matches.get_flag("slim-icc") {
+        opts.slim_icc = true;
+    }
+
+    if let Some(mode) = matches.get_one::<String>("icc-to-cicp") {
+        opts.icc_to_cicp = Some(if mode == "add" {
+            CicpMode::Add
+        } else {
+            CicpMode::Replace
+        });
+    }
+
     #[cfg(feature = "zopfli")]
     if matches.get_flag("zopfli") {
         let iteration_count = *matches.get_one::<NonZeroU64>("iterations").unwrap();
@@ -479,6 +544,82 @@ fn parse_chunk_name(name: &str) -> Result<[u8; 4], String> {
         .map_err(|_| format!("Invalid chunk name {name}"))
 }
 
+const FORBIDDEN_CHUNKS: [[u8; 4]; 5] = [*b"IHDR", *b"IDAT", *b"tRNS", *b"PLTE", *b"IEND"];
+
+/// Parse a comma-separated list of chunks to keep, where 'display' includes the display chunks
+fn parse_keep_list(list: &str) -> Result<IndexSet<[u8; 4]>, String> {
+    let mut keep_display = false;
+    let mut names = list
+        .split(',')
+        .filter_map(|x| {
+            if x.trim() == "display" {
+                keep_display = true;
+                return None;
+            }
+            Some(match parse_chunk_name(x) {
+                Ok(name) if FORBIDDEN_CHUNKS.contains(&name) => Err(format!(
+                    "{x} chunk is controlled internally and may not be explicitly kept"
+                )),
+                name => name,
+            })
+        })
+        .collect::<Result<IndexSet<_>, _>>()?;
+    if keep_display {
+        names.extend(DISPLAY_CHUNKS.iter().copied());
+    }
+    Ok(names)
+}
+
+/// Parse a comma-separated list of chunks to strip
+fn parse_strip_list(list: &str) -> Result<IndexSet<[u8; 4]>, String> {
+    list.split(',')
+        .map(|x| {
+            if x == "safe" || x == "all" {
+                return Err(
+                    "'safe' or 'all' presets for --strip should be used by themselves".to_owned(),
+                );
+            }
+            let name = parse_chunk_name(x)?;
+            if FORBIDDEN_CHUNKS.contains(&name) {
+                return Err(format!("{x} chunk is not allowed to be stripped"));
+            }
+            Ok(name)
+        })
+        .collect()
+}
+
+/// Parse a comma-separated list of EXIF data to strip
+fn parse_exif_list(list: &str) -> Result<Vec<ExifStrip>, String> {
+    list.split(',')
+        .map(|x| match x.trim() {
+            "gps" => Ok(ExifStrip::Gps),
+            "makernote" => Ok(ExifStrip::MakerNote),
+            "serial" => Ok(ExifStrip::SerialNumbers),
+            "thumbnail" => Ok(ExifStrip::Thumbnail),
+            tag => tag
+                .strip_prefix("0x")
+                .or_else(|| tag.strip_prefix("0X"))
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                .map(ExifStrip::Tag)
+                .ok_or_else(|| format!("Invalid EXIF selection {tag}")),
+        })
+        .collect()
+}
+
+/// Build the XMP properties to strip from optional comma-separated strip and keep lists
+fn parse_xmp_lists(strip: Option<&str>, keep: Option<&str>) -> XmpStrip {
+    let mut strip_xmp = XmpStrip::default();
+    if let Some(strip) = strip {
+        strip_xmp.strip = strip.split(',').map(|x| x.trim().to_owned()).collect();
+    }
+    if let Some(keep) = keep {
+        strip_xmp
+            .keep
+            .extend(keep.split(',').map(|x| x.trim().to_owned()));
+    }
+    strip_xmp
+}
+
 fn parse_numeric_range_opts(
     input: &str,
     min_value: u8,
@@ -532,7 +673,12 @@ fn parse_numeric_range_opts(
     Err(ERROR_MESSAGE.to_owned())
 }
 
-fn process_file(input: &InFile, output: &OutFile, opts: &Options) -> OptimizationResult {
+fn process_file(
+    input: &InFile,
+    output: &OutFile,
+    export_frames: Option<Option<usize>>,
+    opts: &Options,
+) -> OptimizationResult {
     if let (Some(max_size), InFile::Path(path)) = (opts.max_decompressed_size, input) {
         if path.metadata().is_ok_and(|m| m.len() > max_size as u64) {
             warn!("{input}: Skipped: File exceeds the maximum size ({max_size} bytes)");
@@ -540,7 +686,10 @@ fn process_file(input: &InFile, output: &OutFile, opts: &Options) -> Optimizatio
         }
     }
 
-    let result = oxipng::optimize(input, output, opts);
+    let result = export_frames.map_or_else(
+        || oxipng::optimize(input, output, opts),
+        |frame| oxipng::export_frames(input, output, frame, opts),
+    );
     match &result {
         Ok(_) => {}
         Err(e @ PngError::ChunkPreventsChanges(_) | e @ PngError::InflatedDataTooLong(_)) => {
@@ -7,7 +7,12 @@ use std::{
 use indexmap::{IndexSet, indexset};
 use log::warn;
 
-use crate::{deflate::Deflater, filters::FilterStrategy, headers::StripChunks};
+#[cfg(feature = "lossy")]
+use crate::reduction::quantize::QuantizeOptions;
+use crate::{
+    deflate::Deflater, exif::ExifStrip, filters::FilterStrategy, headers::StripChunks,
+    icc::CicpMode, recovery::RecoveryMode, text::TextChunk, xmp::XmpStrip,
+};
 
 /// Write destination for [`optimize`][crate::optimize].
 /// You can use [`optimize_from_memory`](crate::optimize_from_memory) to avoid external I/O.
@@ -90,10 +95,31 @@ pub struct Options {
     ///
     /// Default: `false`
     pub fix_errors: bool,
+    /// Recover images with truncated or corrupt data, salvaging as many complete scan lines as
+    /// possible and completing the image according to the mode. Trailing data after the IEND
+    /// chunk is discarded. Each repair is reported as a warning.
+    ///
+    /// Default: `None`
+    pub recover: Option<RecoveryMode>,
     /// Write to output even if there was no improvement in compression.
     ///
     /// Default: `false`
     pub force: bool,
+    /// Reject Apple CgBI images that cannot be converted losslessly, because colors of partially
+    /// transparent pixels were premultiplied by their alpha.
+    ///
+    /// Default: `false`
+    pub strict_cgbi: bool,
+    /// Decode the output and check that it matches the input pixel for pixel, including every
+    /// frame of an animation. Colors of fully transparent pixels are not compared. Lossy options
+    /// are not verified.
+    ///
+    /// If verification fails, [`optimize`][crate::optimize] keeps the original file (copying it
+    /// to the output path if different) and returns [`PngError::VerificationFailed`][crate::PngError::VerificationFailed],
+    /// with the frame (0 for the main image) and the index of the first differing pixel.
+    ///
+    /// Default: `false`
+    pub verify: bool,
     /// Which `FilterStrategy` to try on the file
     ///
     /// Default: `None,Sub,Entropy,Bigrams`
@@ -109,6 +135,22 @@ pub struct Options {
     ///
     /// Default: `false`
     pub optimize_alpha: bool,
+    /// Snap alpha values within this distance of 0 or 255 to fully transparent or fully opaque,
+    /// which is lossy but can allow further reductions.
+    /// A 16-bit alpha channel uses a proportionally scaled threshold.
+    ///
+    /// Default: `None`
+    pub alpha_threshold: Option<u8>,
+    /// Whether to optimize the frames of an APNG, by cropping each frame to the region that
+    /// changed and selecting the best dispose and blend operations.
+    ///
+    /// Default: `false`
+    pub optimize_frames: bool,
+    /// Whether to remove frames of an APNG that are identical to the previous frame, adding their
+    /// delay to the previous frame instead.
+    ///
+    /// Default: `false`
+    pub merge_frames: bool,
     /// Whether to attempt bit depth reduction
     ///
     /// Default: `true`
@@ -136,10 +178,52 @@ pub struct Options {
     ///
     /// Default: `false`
     pub scale_16: bool,
+    /// Whether to quantize images with too many colors for a palette, which is lossy.
+    /// The quantized image is only used if it meets the minimum quality and is smaller than
+    /// the lossless result.
+    ///
+    /// Default: `None`
+    #[cfg(feature = "lossy")]
+    pub quantize: Option<QuantizeOptions>,
     /// Which chunks to strip from the PNG file, if any
     ///
     /// Default: `None`
     pub strip: StripChunks,
+    /// Whether to keep unknown chunks that are not safe to copy by disabling all reductions.
+    /// Otherwise, such chunks are removed if the image data changes.
+    ///
+    /// Default: `false`
+    pub strict_chunks: bool,
+    /// Which data to remove from the eXIf chunk, if it is kept. An eXIf chunk that cannot be
+    /// parsed is stripped entirely.
+    ///
+    /// Default: `[]`
+    pub strip_exif: Vec<ExifStrip>,
+    /// Which properties to remove from the XMP packet, if it is kept. Padding whitespace is also
+    /// removed. An XMP packet that cannot be parsed is left unchanged.
+    ///
+    /// Default: `None`
+    pub strip_xmp: Option<XmpStrip>,
+    /// Whether to use a cICP chunk in place of an ICC profile for a well-known color space,
+    /// such as Display P3, Rec. 2020 or BT.2100 PQ/HLG. Standard sRGB profiles are not affected,
+    /// as these are instead replaced with an sRGB chunk when chunks are being stripped.
+    ///
+    /// Default: `None`
+    pub icc_to_cicp: Option<CicpMode>,
+    /// Whether to remove tags that don't affect color rendering from an ICC profile, such as
+    /// localized descriptions, device information and vendor-private tags
+    ///
+    /// Default: `false`
+    pub slim_icc: bool,
+    /// Text chunks to add to the PNG file, replacing any existing text chunks with the same
+    /// keyword. These are added regardless of `strip`.
+    ///
+    /// Default: `[]`
+    pub set_text: Vec<TextChunk>,
+    /// Keywords of text chunks to remove from the PNG file
+    ///
+    /// Default: `[]`
+    pub remove_text: Vec<String>,
     /// Which DEFLATE (zlib) algorithm to use
     #[cfg_attr(feature = "zopfli", doc = "(e.g. Zopfli)")]
     ///
@@ -159,6 +243,24 @@ pub struct Options {
     ///
     /// Default: `None`
     pub max_decompressed_size: Option<usize>,
+    /// Decompressed size of the input IDAT above which the image is processed a few scan lines at
+    /// a time, rather than decoded in full. This keeps memory usage low for very large images, at
+    /// the cost of less thorough optimization: reductions are limited to bit depth, grayscale,
+    /// alpha and palette, interlacing is kept as it is, and each filter is compressed once.
+    /// Animated and CgBI images are always decoded in full.
+    ///
+    /// Default: `None`
+    pub stream_decompressed_size: Option<usize>,
+    /// Whether to memory map input files rather than reading them into memory. This avoids
+    /// holding a second copy of large files while they are being optimized.
+    ///
+    /// The file must not be modified or truncated by another process while it is mapped. This is
+    /// undefined behavior, and truncation may crash the process. A modification that is detected
+    /// afterwards causes the file to be read again and the optimization restarted, but this is
+    /// only a safeguard. Has no effect unless the `mmap` feature is enabled.
+    ///
+    /// Default: `false`
+    pub memory_map: bool,
 }
 
 impl Options {
@@ -185,6 +287,19 @@ impl Options {
         Self::from_preset(6)
     }
 
+    /// Whether any lossy transformations are enabled
+    #[cfg_attr(not(feature = "sanity-checks"), allow(dead_code))]
+    pub(crate) const fn is_lossy(&self) -> bool {
+        if self.alpha_threshold.is_some() {
+            return true;
+        }
+        #[cfg(feature = "lossy")]
+        if self.quantize.is_some() {
+            return true;
+        }
+        false
+    }
+
     // The following methods make assumptions that they are operating
     // on an `Options` struct generated by the `default` method.
     fn apply_preset_0(mut self) -> Self {
@@ -278,6 +393,9 @@ impl Default for Options {
         // Default settings based on -o 2 from the CLI interface
         Self {
             fix_errors: false,
+            recover: None,
+            strict_cgbi: false,
+            verify: false,
             force: false,
             filters: indexset! {
                 FilterStrategy::NONE,
@@ -287,17 +405,31 @@ impl Default for Options {
             },
             interlace: Some(false),
             optimize_alpha: false,
+            alpha_threshold: None,
+            optimize_frames: false,
+            merge_frames: false,
             bit_depth_reduction: true,
             color_type_reduction: true,
             palette_reduction: true,
             grayscale_reduction: true,
             idat_recoding: true,
             scale_16: false,
+            #[cfg(feature = "lossy")]
+            quantize: None,
             strip: StripChunks::None,
+            strict_chunks: false,
+            strip_exif: Vec::new(),
+            strip_xmp: None,
+            icc_to_cicp: None,
+            slim_icc: false,
+            set_text: Vec::new(),
+            remove_text: Vec::new(),
             deflater: Deflater::Libdeflater { compression: 11 },
             fast_evaluation: true,
             timeout: None,
             max_decompressed_size: None,
+            stream_decompressed_size: None,
+            memory_map: false,
         }
     }
 }
@@ -1,16 +1,21 @@
 use log::warn;
+use rgb::{ComponentMap, RGB16, RGBA8, RGBA16};
 use rustc_hash::FxHashMap;
 use std::{fs, path::Path, sync::Arc};
 
 use crate::{
     Options, PngResult,
     apng::*,
+    cgbi::convert_cgbi,
     colors::{BitDepth, ColorType},
     deflate,
     error::PngError,
     filters::*,
     headers::*,
+    input::InputData,
     interlace::{deinterlace_image, interlace_image},
+    recovery::{recover_image, report_trailing_data, salvage_chunk},
+    reduction::bit_depth::expanded_bit_depth_to_8,
 };
 
 pub(crate) mod scan_lines;
@@ -36,13 +41,26 @@ pub struct PngData {
     pub aux_chunks: Vec<Chunk>,
     /// APNG frames
     pub frames: Vec<Frame>,
+    /// Whether any damage or spec violations were repaired when reading the file
+    pub recovered: bool,
+}
+
+/// The chunks of a PNG file, before the image data is decoded
+#[derive(Debug)]
+pub(crate) struct PngChunks {
+    pub ihdr: IhdrData,
+    pub idat_data: Vec<u8>,
+    pub aux_chunks: Vec<Chunk>,
+    pub frames: Vec<Frame>,
+    pub is_cgbi: bool,
+    pub recovered: bool,
 }
 
 impl PngData {
     /// Create a new `PngData` struct by opening a file
     #[inline]
     pub fn new(filepath: &Path, opts: &Options) -> PngResult<Self> {
-        let byte_data = Self::read_file(filepath)?;
+        let byte_data = InputData::read_file(filepath, opts.memory_map)?;
 
         Self::from_slice(&byte_data, opts)
     }
@@ -53,6 +71,43 @@ impl PngData {
 
     /// Create a new `PngData` struct by reading a slice
     pub fn from_slice(byte_data: &[u8], opts: &Options) -> PngResult<Self> {
+        let PngChunks {
+            ihdr,
+            idat_data,
+            aux_chunks,
+            frames,
+            is_cgbi,
+            mut recovered,
+        } = Self::read_chunks(byte_data, opts)?;
+
+        let (raw, idat_data) = if is_cgbi {
+            if !frames.is_empty() {
+                return Err(PngError::new("Animated CgBI images are not supported"));
+            }
+            convert_cgbi(ihdr, &idat_data, opts.strict_cgbi)?
+        } else {
+            match (PngImage::new(ihdr.clone(), &idat_data), opts.recover) {
+                (Err(_), Some(mode)) => {
+                    recovered = true;
+                    recover_image(ihdr, &idat_data, mode)?
+                }
+                (raw, _) => (raw?, idat_data),
+            }
+        };
+
+        let mut png = Self {
+            idat_data,
+            raw: Arc::new(raw),
+            aux_chunks,
+            frames,
+            recovered,
+        };
+        validate_apng(&mut png, opts.fix_errors)?;
+        Ok(png)
+    }
+
+    /// Read the chunks of a PNG file without decoding the image data
+    pub(crate) fn read_chunks(byte_data: &[u8], opts: &Options) -> PngResult<PngChunks> {
         let mut byte_offset: usize = 0;
         // Test that png header is valid
         let header = byte_data.get(0..8).ok_or(PngError::TruncatedData)?;
@@ -67,7 +122,27 @@ impl PngData {
         let mut aux_chunks: Vec<Chunk> = Vec::new();
         let mut frames: Vec<Frame> = Vec::new();
         let mut sequence_number = 0;
-        while let Some(chunk) = parse_next_chunk(byte_data, &mut byte_offset, opts.fix_errors)? {
+        let mut is_cgbi = false;
+        let mut recovered = false;
+        let mut plte_position = None;
+        loop {
+            let chunk = match parse_next_chunk(byte_data, &mut byte_offset, opts.fix_errors) {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => {
+                    if opts.recover.is_some() {
+                        recovered |= report_trailing_data(byte_data, byte_offset);
+                    }
+                    break;
+                }
+                Err(PngError::TruncatedData) if opts.recover.is_some() => {
+                    recovered = true;
+                    match salvage_chunk(byte_data, &mut byte_offset) {
+                        Some(chunk) => chunk,
+                        None => break,
+                    }
+                }
+                Err(e) => return Err(e),
+            };
             match &chunk.name {
                 b"IDAT" => {
                     if idat_data.is_empty() {
@@ -79,7 +154,19 @@ impl PngData {
                     }
                     idat_data.extend_from_slice(chunk.data);
                 }
+                b"CgBI" => is_cgbi = true,
                 b"IHDR" | b"PLTE" | b"tRNS" => {
+                    if key_chunks.contains_key(&chunk.name) {
+                        warn!(
+                            "Removing duplicate {} chunk",
+                            std::str::from_utf8(&chunk.name).unwrap()
+                        );
+                        recovered = true;
+                        continue;
+                    }
+                    if chunk.name == *b"PLTE" {
+                        plte_position = Some(aux_chunks.len());
+                    }
                     key_chunks.insert(chunk.name, chunk.data.to_owned());
                 }
                 _ if opts.strip.keep(&chunk.name) => {
@@ -98,10 +185,17 @@ impl PngData {
                     }
                     if chunk.name == *b"fcTL" || chunk.name == *b"fdAT" {
                         // Validate the sequence number
-                        if read_be_u32(&chunk.data[0..4]) != sequence_number {
-                            return Err(PngError::APNGOutOfOrder);
+                        let found =
+                            read_be_u32(chunk.data.get(0..4).ok_or(PngError::TruncatedData)?);
+                        if found != sequence_number {
+                            let err = PngError::APNGSequenceMismatch(sequence_number, found);
+                            if !opts.fix_errors {
+                                return Err(err);
+                            }
+                            // Sequence numbers are regenerated on output, so we can continue
+                            warn!("{err}, ignoring");
                         }
-                        sequence_number += 1;
+                        sequence_number = found.wrapping_add(1);
                         if chunk.name == *b"fcTL" && !idat_data.is_empty() {
                             // Only create a Frame if it's after the IDAT (else store it as an aux chunk)
                             frames.push(Frame::from_fctl_data(chunk.data)?);
@@ -133,6 +227,7 @@ impl PngData {
         if idat_data.is_empty() {
             return Err(PngError::ChunkMissing("IDAT"));
         }
+        recovered |= normalize_chunk_order(&mut aux_chunks, plte_position);
         let Some(ihdr_chunk) = key_chunks.remove(b"IHDR") else {
             return Err(PngError::ChunkMissing("IHDR"));
         };
@@ -147,17 +242,28 @@ impl PngData {
             }
         }
 
-        let raw = PngImage::new(ihdr, &idat_data)?;
-
-        // Return the PngData
-        Ok(Self {
+        Ok(PngChunks {
+            ihdr,
             idat_data,
-            raw: Arc::new(raw),
             aux_chunks,
             frames,
+            is_cgbi,
+            recovered,
         })
     }
 
+    /// Construct the data for the acTL chunk, with the frame count matching the current frames
+    fn actl_data(&self, data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        if data.len() >= 4 {
+            // The default image is also a frame if it has its own fcTL chunk
+            let has_default = self.aux_chunks.iter().any(|c| &c.name == b"fcTL");
+            let num_frames = self.frames.len() as u32 + u32::from(has_default);
+            data[0..4].copy_from_slice(&num_frames.to_be_bytes());
+        }
+        data
+    }
+
     /// Format the `PngData` struct into a valid PNG bytestream
     #[must_use]
     pub fn output(&self) -> Vec<u8> {
@@ -185,7 +291,11 @@ impl PngData {
             .iter()
             .filter(|c| !matches!(&c.name, b"bKGD" | b"hIST" | b"tRNS" | b"fcTL"))
         {
-            write_png_block(&chunk.name, &chunk.data, &mut output);
+            if &chunk.name == b"acTL" {
+                write_png_block(&chunk.name, &self.actl_data(&chunk.data), &mut output);
+            } else {
+                write_png_block(&chunk.name, &chunk.data, &mut output);
+            }
         }
         // Palette and transparency
         match &self.raw.ihdr.color_type {
@@ -250,7 +360,11 @@ impl PngData {
 impl PngImage {
     pub fn new(ihdr: IhdrData, compressed_data: &[u8]) -> PngResult<Self> {
         let raw_data = deflate::inflate(compressed_data, ihdr.raw_data_size())?;
+        Self::from_filtered(ihdr, raw_data)
+    }
 
+    /// Construct an image from decompressed data that is still filtered
+    pub(crate) fn from_filtered(ihdr: IhdrData, raw_data: Vec<u8>) -> PngResult<Self> {
         // Reject files with incorrect width/height or truncated data
         if raw_data.len() != ihdr.raw_data_size() {
             return Err(PngError::TruncatedData);
@@ -283,6 +397,98 @@ impl PngImage {
         })
     }
 
+    /// Convert the image to 8-bit RGBA pixels in non-interlaced order
+    /// Returns None if the image is 16-bit
+    #[must_use]
+    pub fn rgba_pixels(&self) -> Option<Vec<RGBA8>> {
+        if self.ihdr.bit_depth == BitDepth::Sixteen {
+            return None;
+        }
+        let deinterlaced = self.change_interlacing(false);
+        let png = deinterlaced.as_ref().unwrap_or(self);
+        let expanded = expanded_bit_depth_to_8(png);
+        let png = expanded.as_ref().unwrap_or(png);
+
+        let opaque = |trns: bool| if trns { 0 } else { 255 };
+        Some(match &png.ihdr.color_type {
+            ColorType::Grayscale { transparent_shade } => png
+                .data
+                .iter()
+                .map(|&v| RGBA8::new(v, v, v, opaque(*transparent_shade == Some(v.into()))))
+                .collect(),
+            ColorType::RGB { transparent_color } => png
+                .data
+                .chunks_exact(3)
+                .map(|px| {
+                    let trns = *transparent_color
+                        == Some(RGB16::new(px[0].into(), px[1].into(), px[2].into()));
+                    RGBA8::new(px[0], px[1], px[2], opaque(trns))
+                })
+                .collect(),
+            ColorType::Indexed { palette } => png
+                .data
+                .iter()
+                .map(|&i| {
+                    // Out of range indices are treated as opaque black
+                    palette
+                        .get(i as usize)
+                        .copied()
+                        .unwrap_or(RGBA8::new(0, 0, 0, 255))
+                })
+                .collect(),
+            ColorType::GrayscaleAlpha => png
+                .data
+                .chunks_exact(2)
+                .map(|px| RGBA8::new(px[0], px[0], px[0], px[1]))
+                .collect(),
+            ColorType::RGBA => png
+                .data
+                .chunks_exact(4)
+                .map(|px| RGBA8::new(px[0], px[1], px[2], px[3]))
+                .collect(),
+        })
+    }
+
+    /// Convert the image to 16-bit RGBA pixels in non-interlaced order, allowing images of any
+    /// bit depth to be compared exactly
+    #[must_use]
+    pub fn rgba16_pixels(&self) -> Vec<RGBA16> {
+        if let Some(pixels) = self.rgba_pixels() {
+            return pixels
+                .into_iter()
+                .map(|px| px.map(|v| u16::from(v) * 257))
+                .collect();
+        }
+        let deinterlaced = self.change_interlacing(false);
+        let png = deinterlaced.as_ref().unwrap_or(self);
+        let values: Vec<_> = png.data.chunks_exact(2).map(read_be_u16).collect();
+
+        let opaque = |trns: bool| if trns { 0 } else { u16::MAX };
+        match &png.ihdr.color_type {
+            ColorType::Grayscale { transparent_shade } => values
+                .iter()
+                .map(|&v| RGBA16::new(v, v, v, opaque(*transparent_shade == Some(v))))
+                .collect(),
+            ColorType::RGB { transparent_color } => values
+                .chunks_exact(3)
+                .map(|px| {
+                    let trns = *transparent_color == Some(RGB16::new(px[0], px[1], px[2]));
+                    RGBA16::new(px[0], px[1], px[2], opaque(trns))
+                })
+                .collect(),
+            ColorType::GrayscaleAlpha => values
+                .chunks_exact(2)
+                .map(|px| RGBA16::new(px[0], px[0], px[0], px[1]))
+                .collect(),
+            ColorType::RGBA => values
+                .chunks_exact(4)
+                .map(|px| RGBA16::new(px[0], px[1], px[2], px[3]))
+                .collect(),
+            // 16-bit indexed images are rejected when reading
+            ColorType::Indexed { .. } => unreachable!(),
+        }
+    }
+
     /// Return the number of channels in the image, based on color type
     #[inline]
     #[must_use]
@@ -366,68 +572,11 @@ impl PngImage {
             0
         };
 
-        let mut prev_line = Vec::new();
-        let mut prev_pass: Option<u8> = None;
-        // For heuristic strategies, keep track of the actual filter used for each line
-        let mut filters_used = Vec::new();
-        let mut strategy_evaluator = strategy.evaluator();
-        for (i, line) in self.scan_lines(false).enumerate() {
-            if prev_pass != line.pass || prev_line.is_empty() {
-                prev_line = vec![0; line.data.len()];
-                prev_pass = line.pass;
-            }
-            // Alpha optimisation may alter the line data, so we need a mutable copy of it
-            let mut line_data = line.data.to_vec();
-
-            if let FilterStrategy::Basic(filter) = strategy {
-                // Standard filters
-                filter.filter_line(bpp, &mut line_data, &prev_line, &mut output, alpha_bytes);
-                prev_line = line_data;
-                continue;
-            } else if let FilterStrategy::Predefined(lines) = &strategy {
-                // Predefined filter for each line
-                let filter = lines.get(i).unwrap_or(&RowFilter::None);
-                filter.filter_line(bpp, &mut line_data, &prev_line, &mut output, alpha_bytes);
-                prev_line = line_data;
-                continue;
-            }
-
-            // Heuristic filter selection strategies
-
-            let mut best_filter = RowFilter::None;
-            if line_data.iter().all(|&x| x == 0) {
-                // Assume None if the line is all zeros
-                best_filter.filter_line(bpp, &mut line_data, &prev_line, &mut output, alpha_bytes);
-                prev_line = line_data;
-                filters_used.push(best_filter);
-                continue;
-            }
-
-            let line_len = line.data.len() + 1;
-            let mut best_line = vec![0; line_len];
-            let mut best_line_raw = Vec::with_capacity(line.data.len());
-            let offset = output.len();
-            let evaluator = strategy_evaluator.as_mut().unwrap();
-            evaluator.reset(line_len);
-            for f in RowFilter::ALL {
-                f.filter_line(bpp, &mut line_data, &prev_line, &mut output, alpha_bytes);
-                if evaluator.evaluate(&output, offset) {
-                    best_line.clone_from_slice(&output[offset..]);
-                    best_line_raw.clone_from(&line_data);
-                    best_filter = f;
-                }
-                output.truncate(offset);
-            }
-            output.extend_from_slice(&best_line);
-            prev_line = best_line_raw;
-            filters_used.push(best_filter);
-        }
-
-        if filters_used.is_empty() {
-            (output, strategy)
-        } else {
-            (output, FilterStrategy::Predefined(filters_used))
+        let mut line_filter = LineFilter::new(strategy, bpp, alpha_bytes);
+        for line in self.scan_lines(false) {
+            line_filter.filter_line(line.data, line.pass, &mut output);
         }
+        (output, line_filter.finish())
     }
 }
 
@@ -1,4 +1,4 @@
-use crate::png::PngImage;
+use crate::{headers::IhdrData, png::PngImage};
 
 /// An iterator over the scan lines of a PNG image
 #[derive(Debug, Clone)]
@@ -51,8 +51,9 @@ impl<'a> Iterator for ScanLines<'a> {
 }
 
 #[derive(Debug, Clone)]
-/// An iterator over the scan line locations of a PNG image
-struct ScanLineRanges {
+/// An iterator over the scan line locations of a PNG image, yielding the byte length, pass and
+/// number of pixels of each line
+pub(crate) struct ScanLineRanges {
     /// Current pass number, and 0-indexed row within the pass
     pass: Option<(u8, u32)>,
     bits_per_pixel: usize,
@@ -64,16 +65,22 @@ struct ScanLineRanges {
 
 impl ScanLineRanges {
     pub fn new(png: &PngImage, has_filter: bool) -> Self {
+        Self::with_len(&png.ihdr, png.data.len(), has_filter)
+    }
+
+    /// Iterate over the scan lines of filtered image data for the given header, without needing
+    /// the data itself
+    pub const fn from_ihdr(ihdr: &IhdrData) -> Self {
+        Self::with_len(ihdr, ihdr.raw_data_size(), true)
+    }
+
+    const fn with_len(ihdr: &IhdrData, len: usize, has_filter: bool) -> Self {
         Self {
-            bits_per_pixel: png.ihdr.bpp(),
-            width: png.ihdr.width,
-            height: png.ihdr.height,
-            left: png.data.len(),
-            pass: if png.ihdr.interlaced {
-                Some((1, 0))
-            } else {
-                None
-            },
+            bits_per_pixel: ihdr.bpp(),
+            width: ihdr.width,
+            height: ihdr.height,
+            left: len,
+            pass: if ihdr.interlaced { Some((1, 0)) } else { None },
             has_filter,
         }
     }
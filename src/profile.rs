@@ -0,0 +1,201 @@
+use std::{fs, path::Path};
+
+use indexmap::{IndexMap, IndexSet};
+use oxipng::{CicpMode, ExifStrip, Options, StripChunks, XmpStrip};
+
+use crate::{
+    parse_chunk_name, parse_exif_list, parse_keep_list, parse_strip_list, parse_xmp_lists,
+};
+
+/// The built-in profiles, in the same format as a config file
+const BUILTIN_PROFILES: &str = "
+# Keep only chunks that affect the image appearance
+[web]
+keep = display
+iCCP = recompress
+
+# Keep everything except thumbnails
+[archive]
+strip-exif = thumbnail
+strip-xmp = xmp:Thumbnails,xap:Thumbnails,xmpGImg
+
+# Keep only the physical dimensions and color profile
+[print]
+keep = pHYs,iCCP
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What to do with a specific chunk
+enum ChunkAction {
+    /// Keep the chunk
+    Keep,
+    /// Strip the chunk
+    Drop,
+    /// Keep the chunk, minimizing its data (iCCP, zTXt or iTXt only)
+    Recompress,
+    /// Replace the chunk with a more compact equivalent (iCCP only)
+    Convert,
+}
+
+#[derive(Debug, Clone, Default)]
+/// A named set of metadata handling options
+pub struct StripProfile {
+    /// Chunks to keep, stripping all others
+    keep: Option<IndexSet<[u8; 4]>>,
+    /// Chunks to strip
+    strip: IndexSet<[u8; 4]>,
+    /// Actions for specific chunks, which take precedence over the lists
+    actions: IndexMap<[u8; 4], ChunkAction>,
+    strip_exif: Vec<ExifStrip>,
+    strip_xmp: Option<XmpStrip>,
+}
+
+impl StripProfile {
+    /// Resolve the profile into the options
+    pub fn apply(&self, opts: &mut Options) {
+        let is_dropped = |action: &&ChunkAction| **action == ChunkAction::Drop;
+        let dropped = self.actions.iter().filter(|(_, a)| is_dropped(a));
+        let dropped: IndexSet<_> = self.strip.iter().chain(dropped.map(|(n, _)| n)).collect();
+        opts.strip = match &self.keep {
+            Some(keep) => {
+                let kept = self.actions.iter().filter(|(_, a)| !is_dropped(a));
+                let names = keep.iter().chain(kept.map(|(n, _)| n));
+                StripChunks::Keep(names.filter(|n| !dropped.contains(n)).copied().collect())
+            }
+            None if dropped.is_empty() => StripChunks::None,
+            None => StripChunks::Strip(dropped.into_iter().copied().collect()),
+        };
+        match self.actions.get(b"iCCP") {
+            Some(ChunkAction::Recompress) => opts.slim_icc = true,
+            Some(ChunkAction::Convert) => opts.icc_to_cicp = Some(CicpMode::Replace),
+            _ => {}
+        }
+        opts.strip_exif.clone_from(&self.strip_exif);
+        opts.strip_xmp.clone_from(&self.strip_xmp);
+    }
+}
+
+/// Parse profiles from a config file, which contains sections of `key = value` lines
+///
+/// Each section is headed by the profile name in square brackets. The keys are `keep`, `strip`,
+/// `strip-exif`, `strip-xmp` and `keep-xmp`, taking the same values as the command line options,
+/// or a chunk name to specify its action: `keep`, `drop`, `recompress` or `convert`. Lines
+/// starting with `#` are comments.
+fn parse_profiles(config: &str) -> Result<IndexMap<String, StripProfile>, String> {
+    let mut profiles = IndexMap::new();
+    let mut current = None;
+    for (i, line) in config.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let err = |msg: String| format!("Line {}: {msg}", i + 1);
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.trim().to_owned();
+            profiles.insert(name.clone(), StripProfile::default());
+            current = Some(name);
+            continue;
+        }
+        let Some(profile) = current.as_ref().and_then(|name| profiles.get_mut(name)) else {
+            return Err(err("Expected a [profile] heading".to_owned()));
+        };
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| err(format!("Expected key = value, found {line}")))?;
+        let (key, value) = (key.trim(), value.trim());
+        match key {
+            "keep" => profile.keep = Some(parse_keep_list(value).map_err(err)?),
+            "strip" => profile.strip = parse_strip_list(value).map_err(err)?,
+            "strip-exif" => profile.strip_exif = parse_exif_list(value).map_err(err)?,
+            "strip-xmp" => {
+                profile.strip_xmp =
+                    Some(parse_xmp_lists(Some(value).filter(|v| !v.is_empty()), None));
+            }
+            "keep-xmp" => {
+                let strip_xmp = profile
+                    .strip_xmp
+                    .as_mut()
+                    .ok_or_else(|| err("keep-xmp requires strip-xmp".to_owned()))?;
+                strip_xmp
+                    .keep
+                    .extend(value.split(',').map(|x| x.trim().to_owned()));
+            }
+            _ => {
+                let name = parse_chunk_name(key).map_err(err)?;
+                let action = match value {
+                    "keep" => ChunkAction::Keep,
+                    "drop" => ChunkAction::Drop,
+                    "recompress" if matches!(&name, b"iCCP" | b"zTXt" | b"iTXt") => {
+                        ChunkAction::Recompress
+                    }
+                    "convert" if &name == b"iCCP" => ChunkAction::Convert,
+                    _ => return Err(err(format!("Invalid action {value} for {key} chunk"))),
+                };
+                profile.actions.insert(name, action);
+            }
+        }
+    }
+    Ok(profiles)
+}
+
+/// Get the built-in profiles
+pub fn builtin_profiles() -> IndexMap<String, StripProfile> {
+    parse_profiles(BUILTIN_PROFILES).unwrap()
+}
+
+/// Load profiles from a config file
+pub fn load_profiles(path: &Path) -> Result<IndexMap<String, StripProfile>, String> {
+    let config =
+        fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+    parse_profiles(&config).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin() {
+        let profiles = builtin_profiles();
+        let mut opts = Options::default();
+        profiles["web"].apply(&mut opts);
+        assert!(matches!(&opts.strip, StripChunks::Keep(k) if k.contains(b"iCCP")));
+        assert!(opts.slim_icc);
+
+        let mut opts = Options::default();
+        profiles["archive"].apply(&mut opts);
+        assert_eq!(opts.strip, StripChunks::None);
+        assert_eq!(opts.strip_exif, [ExifStrip::Thumbnail]);
+        assert_eq!(opts.strip_xmp.unwrap().strip.len(), 3);
+
+        let mut opts = Options::default();
+        profiles["print"].apply(&mut opts);
+        assert_eq!(opts.strip, StripChunks::Keep([*b"pHYs", *b"iCCP"].into()));
+    }
+
+    #[test]
+    fn actions() {
+        let config =
+            "[custom]\nkeep = display\nstrip = pHYs\neXIf = keep\nsRGB = drop\niCCP = convert\n";
+        let mut opts = Options::default();
+        parse_profiles(config).unwrap()["custom"].apply(&mut opts);
+        let StripChunks::Keep(names) = &opts.strip else {
+            panic!("Expected keep list");
+        };
+        assert!(names.contains(b"eXIf") && names.contains(b"cICP"));
+        assert!(!names.contains(b"pHYs") && !names.contains(b"sRGB"));
+        assert_eq!(opts.icc_to_cicp, Some(CicpMode::Replace));
+
+        let mut opts = Options::default();
+        parse_profiles("[custom]\ntIME = drop\n").unwrap()["custom"].apply(&mut opts);
+        assert_eq!(opts.strip, StripChunks::Strip([*b"tIME"].into()));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse_profiles("keep = display").is_err());
+        assert!(parse_profiles("[custom]\nkeep display").is_err());
+        assert!(parse_profiles("[custom]\nkeep = IDAT").is_err());
+        assert!(parse_profiles("[custom]\neXIf = convert").is_err());
+    }
+}
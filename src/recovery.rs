@@ -0,0 +1,127 @@
+use log::warn;
+use miniz_oxide::inflate::{
+    TINFLStatus,
+    core::{DecompressorOxide, decompress, inflate_flags},
+};
+
+use crate::{
+    PngError, PngResult,
+    deflate::Deflater,
+    headers::{IhdrData, RawChunk, read_be_u32},
+    png::{PngImage, scan_lines::ScanLines},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How to complete an image when some scan lines could not be recovered from damaged data
+pub enum RecoveryMode {
+    /// Fill the missing scan lines with zeros, which are black or transparent
+    Pad,
+    /// Reduce the image height to the recovered scan lines. Interlaced images are padded instead,
+    /// as their scan lines do not cover a contiguous area.
+    Crop,
+}
+
+/// Salvage what remains of a chunk that runs past the end of the data. Only IDAT data is
+/// retained, as other chunks are not usable when incomplete.
+pub fn salvage_chunk<'a>(byte_data: &'a [u8], byte_offset: &mut usize) -> Option<RawChunk<'a>> {
+    let remaining = byte_data.get(*byte_offset..).unwrap_or_default();
+    *byte_offset = byte_data.len();
+    if let Some(header) = remaining.get(0..8) {
+        let length = read_be_u32(&header[0..4]) as usize;
+        let name: [u8; 4] = header[4..8].try_into().unwrap();
+        match &name {
+            b"IDAT" => {
+                let data = &remaining[8..remaining.len().min(length.saturating_add(8))];
+                warn!(
+                    "IDAT chunk is truncated, recovered {} of {length} bytes",
+                    data.len()
+                );
+                return Some(RawChunk { name, data });
+            }
+            b"IEND" => {
+                warn!("IEND chunk is truncated, rebuilding it");
+                return None;
+            }
+            _ => warn!(
+                "Dropping truncated {} chunk",
+                String::from_utf8_lossy(&name)
+            ),
+        }
+    } else if !remaining.is_empty() {
+        warn!(
+            "Ignoring {} bytes of incomplete chunk data",
+            remaining.len()
+        );
+    }
+    warn!("IEND chunk is missing, rebuilding it");
+    None
+}
+
+/// Report any data following the IEND chunk, whose name is at the given offset. Returns true if
+/// there was trailing data.
+pub fn report_trailing_data(byte_data: &[u8], iend_offset: usize) -> bool {
+    let trailing = byte_data.len().saturating_sub(iend_offset + 8);
+    if trailing > 0 {
+        warn!("Ignoring {trailing} bytes of trailing data after IEND");
+    }
+    trailing > 0
+}
+
+/// Decode damaged image data, salvaging as many complete scan lines as possible and completing
+/// the image according to the mode
+///
+/// Returns the image along with valid compressed data to use in place of the original
+pub fn recover_image(
+    ihdr: IhdrData,
+    compressed_data: &[u8],
+    mode: RecoveryMode,
+) -> PngResult<(PngImage, Vec<u8>)> {
+    let expected = ihdr.raw_data_size();
+    let mut data = vec![0; expected];
+    let mut decompressor = Box::<DecompressorOxide>::default();
+    let flags = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+        | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    let (status, _, len) = decompress(&mut decompressor, compressed_data, &mut data, 0, flags);
+    match status {
+        TINFLStatus::Done if len < expected => warn!("Image data is shorter than expected"),
+        TINFLStatus::Done => {}
+        TINFLStatus::Adler32Mismatch => warn!("Image data has an incorrect checksum"),
+        TINFLStatus::HasMoreOutput => warn!("Ignoring excess image data"),
+        TINFLStatus::NeedsMoreInput | TINFLStatus::FailedCannotMakeProgress => {
+            warn!("Image data is truncated");
+        }
+        _ => warn!("Image data is corrupt"),
+    }
+
+    // Find the complete scan lines, stopping at any with an invalid filter type
+    let mut image = PngImage { ihdr, data };
+    let mut recovered_len = 0;
+    let mut recovered_lines: u32 = 0;
+    let mut total_lines = 0;
+    for line in ScanLines::new(&image, true) {
+        let end = recovered_len + line.data.len() + 1;
+        if recovered_lines == total_lines && end <= len && line.filter <= 4 {
+            recovered_len = end;
+            recovered_lines += 1;
+        }
+        total_lines += 1;
+    }
+    if recovered_lines == 0 {
+        return Err(PngError::new(
+            "No scan lines could be recovered from the image data",
+        ));
+    }
+    if recovered_lines < total_lines {
+        let msg = format!("Recovered {recovered_lines} of {total_lines} scan lines");
+        if mode == RecoveryMode::Crop && !image.ihdr.interlaced {
+            warn!("{msg}, cropping the image height to {recovered_lines}");
+            image.ihdr.height = recovered_lines;
+            image.data.truncate(recovered_len);
+        } else {
+            warn!("{msg}, padding the remainder with zeros");
+            image.data[recovered_len..].fill(0);
+        }
+    }
+    let idat_data = Deflater::Libdeflater { compression: 1 }.deflate(&image.data, None)?;
+    Ok((PngImage::from_filtered(image.ihdr, image.data)?, idat_data))
+}
@@ -1,4 +1,4 @@
-use rgb::RGB16;
+use rgb::{RGB16, RGBA8};
 
 use crate::{
     colors::{BitDepth, ColorType},
@@ -31,6 +31,97 @@ pub fn cleaned_alpha_channel(png: &PngImage) -> Option<PngImage> {
     })
 }
 
+/// Snap alpha values within `threshold` of fully transparent or fully opaque to 0 or 255,
+/// returning the new image along with the number of pixels that were changed
+#[must_use]
+pub fn snapped_alpha_channel(png: &PngImage, threshold: u8) -> Option<(PngImage, usize)> {
+    let snap = |a: u16, max: u16, threshold: u16| {
+        if a <= threshold {
+            0
+        } else if a >= max - threshold {
+            max
+        } else {
+            a
+        }
+    };
+
+    if let ColorType::Indexed { palette } = &png.ihdr.color_type {
+        let mut changed = [false; 256];
+        let palette: Vec<_> = palette
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let a = snap(c.a.into(), 255, threshold.into()) as u8;
+                changed[i] = a != c.a;
+                RGBA8 { a, ..*c }
+            })
+            .collect();
+        if !changed.contains(&true) {
+            return None;
+        }
+        // Count the pixels which use any of the changed palette entries
+        let bits = png.ihdr.bit_depth as usize;
+        let mask = ((1_u16 << bits) - 1) as u8;
+        let mut count = 0;
+        for line in png.scan_lines(false) {
+            for i in 0..line.num_pixels {
+                let byte = line.data[i * bits / 8];
+                let idx = (byte >> (8 - bits - (i * bits) % 8)) & mask;
+                count += usize::from(changed[idx as usize]);
+            }
+        }
+        return Some((
+            PngImage {
+                data: png.data.clone(),
+                ihdr: IhdrData {
+                    color_type: ColorType::Indexed { palette },
+                    ..png.ihdr
+                },
+            },
+            count,
+        ));
+    }
+
+    if !png.ihdr.color_type.has_alpha() {
+        return None;
+    }
+    let byte_depth = png.bytes_per_channel();
+    let bpp = png.channels_per_pixel() * byte_depth;
+    let (max, threshold) = match png.ihdr.bit_depth {
+        BitDepth::Sixteen => (u16::MAX, u16::from(threshold) * 257),
+        _ => (255, u16::from(threshold)),
+    };
+
+    let mut data = png.data.clone();
+    let mut count = 0;
+    for pixel in data.chunks_exact_mut(bpp) {
+        let alpha = &mut pixel[bpp - byte_depth..];
+        let a = match alpha {
+            [a] => u16::from(*a),
+            _ => u16::from_be_bytes([alpha[0], alpha[1]]),
+        };
+        let snapped = snap(a, max, threshold);
+        if snapped != a {
+            match alpha {
+                [a] => *a = snapped as u8,
+                _ => alpha.copy_from_slice(&snapped.to_be_bytes()),
+            }
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return None;
+    }
+
+    Some((
+        PngImage {
+            data,
+            ihdr: png.ihdr.clone(),
+        },
+        count,
+    ))
+}
+
 #[must_use]
 pub fn reduced_alpha_channel(png: &PngImage, optimize_alpha: bool) -> Option<PngImage> {
     if !png.ihdr.color_type.has_alpha() {
@@ -1,3 +1,5 @@
+use rgb::RGB16;
+
 use crate::{
     colors::{BitDepth, ColorType},
     headers::IhdrData,
@@ -21,10 +23,15 @@ pub fn reduced_bit_depth_16_to_8(png: &PngImage, force_scale: bool) -> Option<Pn
         return None;
     }
 
+    // A transparency value that can't be reduced can't match any pixel, so it is simply dropped
+    let reduce = |v: u16| {
+        let [hi, lo] = v.to_be_bytes();
+        (hi == lo).then_some(u16::from(hi))
+    };
     Some(PngImage {
         data: png.data.chunks_exact(2).map(|pair| pair[0]).collect(),
         ihdr: IhdrData {
-            color_type: png.ihdr.color_type.clone(),
+            color_type: reduced_transparency(&png.ihdr.color_type, reduce),
             bit_depth: BitDepth::Eight,
             ..png.ihdr
         },
@@ -51,18 +58,61 @@ pub fn scaled_bit_depth_16_to_8(png: &PngImage) -> Option<PngImage> {
             let val = f32::from(u16::from_be_bytes([pair[0], pair[1]]));
             (val * (255.0 / 65535.0)).round() as u8
         })
+        .collect::<Vec<_>>();
+
+    let scale = |v: u16| Some((f32::from(v) * (255.0 / 65535.0)).round() as u16);
+    let color_type = reduced_transparency(&png.ihdr.color_type, scale);
+
+    // If an opaque color is scaled to the same value as the transparency value, it would become
+    // transparent, so the image can't be scaled without changing its transparency
+    let key = |color_type: &ColorType| match color_type {
+        ColorType::Grayscale {
+            transparent_shade: Some(t),
+        } => vec![*t],
+        ColorType::RGB {
+            transparent_color: Some(t),
+        } => vec![t.r, t.g, t.b],
+        _ => Vec::new(),
+    };
+    let old_key: Vec<u8> = key(&png.ihdr.color_type)
+        .iter()
+        .flat_map(|v| v.to_be_bytes())
         .collect();
+    let new_key: Vec<u8> = key(&color_type).iter().map(|&v| v as u8).collect();
+    if !new_key.is_empty()
+        && png
+            .data
+            .chunks_exact(old_key.len())
+            .zip(data.chunks_exact(new_key.len()))
+            .any(|(old, new)| new == new_key && old != old_key)
+    {
+        return None;
+    }
 
     Some(PngImage {
         data,
         ihdr: IhdrData {
-            color_type: png.ihdr.color_type.clone(),
+            color_type,
             bit_depth: BitDepth::Eight,
             ..png.ihdr
         },
     })
 }
 
+/// Convert the 16-bit transparency value of a color type to 8-bit using the given function
+fn reduced_transparency(color_type: &ColorType, f: impl Fn(u16) -> Option<u16>) -> ColorType {
+    match color_type {
+        ColorType::Grayscale { transparent_shade } => ColorType::Grayscale {
+            transparent_shade: transparent_shade.and_then(&f),
+        },
+        ColorType::RGB { transparent_color } => ColorType::RGB {
+            transparent_color: transparent_color
+                .and_then(|t| Some(RGB16::new(f(t.r)?, f(t.g)?, f(t.b)?))),
+        },
+        _ => color_type.clone(),
+    }
+}
+
 /// Attempt to reduce an 8-bit image to a lower bit depth, returning the reduced image if successful
 #[must_use]
 pub fn reduced_bit_depth_8_or_less(png: &PngImage) -> Option<PngImage> {
@@ -114,6 +164,15 @@ pub fn reduced_bit_depth_8_or_less(png: &PngImage) -> Option<PngImage> {
         minimum_bits
     };
 
+    Some(packed_bit_depth(png, minimum_bits))
+}
+
+/// Pack an 8-bit single channel image into the given number of bits per pixel
+///
+/// The caller must ensure that every value in the image is representable at the new bit depth
+#[must_use]
+pub fn packed_bit_depth(png: &PngImage, minimum_bits: usize) -> PngImage {
+    debug_assert!(png.ihdr.bit_depth == BitDepth::Eight && png.channels_per_pixel() == 1);
     let mut reduced = Vec::with_capacity(png.data.len());
     let mask = (1 << minimum_bits) - 1;
     for line in png.scan_lines(false) {
@@ -155,14 +214,14 @@ pub fn reduced_bit_depth_8_or_less(png: &PngImage) -> Option<PngImage> {
         png.ihdr.color_type.clone()
     };
 
-    Some(PngImage {
+    PngImage {
         data: reduced,
         ihdr: IhdrData {
             color_type,
             bit_depth: (minimum_bits as u8).try_into().unwrap(),
             ..png.ihdr
         },
-    })
+    }
 }
 
 /// Expand a 1/2/4-bit image to 8-bit, returning the expanded image if successful
@@ -1,6 +1,8 @@
-use std::sync::Arc;
+use std::{borrow::Cow, sync::Arc};
 
-use crate::{Deadline, Deflater, Options, evaluate::Evaluator, png::PngImage};
+use log::info;
+
+use crate::{Deadline, Deflater, Options, evaluate::Evaluator, headers::IhdrData, png::PngImage};
 
 pub mod alpha;
 use crate::alpha::*;
@@ -10,6 +12,10 @@ pub mod color;
 use crate::color::*;
 pub mod palette;
 use crate::palette::*;
+#[cfg(feature = "lossy")]
+pub mod quantize;
+#[cfg(feature = "lossy")]
+use crate::quantize::*;
 
 pub(crate) fn perform_reductions(
     mut png: Arc<PngImage>,
@@ -33,6 +39,10 @@ pub(crate) fn perform_reductions(
         }
     }
 
+    // If an alpha threshold is set, snap near-transparent and near-opaque pixels before continuing
+    // This is lossy, but can allow alpha or color type reductions which may not have been possible
+    snap_alpha(&mut png, opts, deadline);
+
     // If alpha optimization is enabled, clean the alpha channel before continuing
     // This can allow some color type reductions which may not have been possible otherwise
     if opts.optimize_alpha && !deadline.passed() {
@@ -134,6 +144,26 @@ pub(crate) fn perform_reductions(
         }
     }
 
+    // If the image has too many colors to be indexed losslessly, attempt lossy quantization
+    #[cfg(feature = "lossy")]
+    if indexed.is_none()
+        && opts.color_type_reduction
+        && opts.palette_reduction
+        && !deadline.passed()
+    {
+        if let Some(reduced) = opts
+            .quantize
+            .as_ref()
+            .and_then(|quantize| quantized_to_indexed(&png, quantize))
+        {
+            // This must always be evaluated, it should never become the baseline
+            let new = Arc::new(sorted_palette(&reduced).unwrap_or(reduced));
+            eval.try_image_with_description(new.clone(), "Indexed (quantized)");
+            evaluation_added = true;
+            indexed = Some(new);
+        }
+    }
+
     // Attempt to sort the palette using the ezeng method
     if !cheap && opts.palette_reduction && !deadline.passed() {
         // Make sure we use the `indexed` var as input if it exists
@@ -177,3 +207,142 @@ pub(crate) fn perform_reductions(
     }
     baseline
 }
+
+/// Perform reductions on the images of an APNG, where every frame must share the same format.
+///
+/// The pixels of all frames are gathered into a single image in order to determine one reduction
+/// that is valid for all of them. There is no evaluation here: each reduction is applied whenever
+/// it is possible. Returns the reduced images in the same order, or None if nothing was changed.
+pub(crate) fn perform_apng_reductions(
+    images: &[PngImage],
+    opts: &Options,
+    deadline: &Deadline,
+) -> Option<Vec<PngImage>> {
+    let first = &images.first()?.ihdr;
+    // Interlaced frames would need to be deinterlaced before their pixels could be gathered
+    if first.interlaced {
+        return None;
+    }
+    // Sub-byte pixels must be expanded in order to be gathered, which is only worthwhile if they
+    // can be reduced again afterwards
+    let expanded: Vec<Cow<PngImage>> = if (first.bit_depth as u8) < 8 {
+        if !opts.bit_depth_reduction {
+            return None;
+        }
+        images
+            .iter()
+            .map(|png| Cow::Owned(expanded_bit_depth_to_8(png).unwrap()))
+            .collect()
+    } else {
+        images.iter().map(Cow::Borrowed).collect()
+    };
+
+    // Combine all pixels into a single row
+    let pixels: usize = images
+        .iter()
+        .map(|png| png.ihdr.width as usize * png.ihdr.height as usize)
+        .sum();
+    let mut png = Arc::new(PngImage {
+        ihdr: IhdrData {
+            width: u32::try_from(pixels).ok()?,
+            height: 1,
+            ..expanded[0].ihdr.clone()
+        },
+        data: expanded
+            .iter()
+            .flat_map(|png| png.data.iter().copied())
+            .collect(),
+    });
+
+    snap_alpha(&mut png, opts, deadline);
+
+    if opts.optimize_alpha && !deadline.passed() {
+        if let Some(reduced) = cleaned_alpha_channel(&png) {
+            png = Arc::new(reduced);
+        }
+    }
+
+    if opts.bit_depth_reduction && !deadline.passed() {
+        if let Some(reduced) = reduced_bit_depth_16_to_8(&png, opts.scale_16) {
+            png = Arc::new(reduced);
+        }
+    }
+
+    if opts.color_type_reduction && opts.grayscale_reduction && !deadline.passed() {
+        if let Some(reduced) = reduced_rgb_to_grayscale(&png) {
+            png = Arc::new(reduced);
+        }
+    }
+
+    if opts.palette_reduction && !deadline.passed() {
+        if let Some(reduced) = reduced_palette(&png, opts.optimize_alpha) {
+            png = Arc::new(reduced);
+        }
+    }
+
+    if opts.color_type_reduction && !deadline.passed() {
+        if let Some(reduced) = reduced_alpha_channel(&png, opts.optimize_alpha) {
+            png = Arc::new(reduced);
+        }
+    }
+
+    if opts.color_type_reduction && opts.palette_reduction && !deadline.passed() {
+        if let Some(reduced) = reduced_to_indexed(&png, opts.grayscale_reduction) {
+            png = Arc::new(reduced);
+        }
+    }
+
+    if opts.palette_reduction && !deadline.passed() {
+        if let Some(reduced) = sorted_palette(&png) {
+            png = Arc::new(reduced);
+        }
+    }
+
+    // Split the pixels back into the individual frames
+    let bytes_per_pixel = png.data.len() / pixels;
+    let mut offset = 0;
+    let mut reduced: Vec<PngImage> = images
+        .iter()
+        .map(|image| {
+            let len = image.ihdr.width as usize * image.ihdr.height as usize * bytes_per_pixel;
+            offset += len;
+            PngImage {
+                ihdr: IhdrData {
+                    width: image.ihdr.width,
+                    height: image.ihdr.height,
+                    ..png.ihdr.clone()
+                },
+                data: png.data[offset - len..offset].to_vec(),
+            }
+        })
+        .collect();
+
+    // The minimum bit depth is determined from the combined pixels and then applied to each frame
+    if opts.bit_depth_reduction && !deadline.passed() {
+        if let Some(combined) = reduced_bit_depth_8_or_less(&png) {
+            let bits = combined.ihdr.bit_depth as usize;
+            reduced = reduced
+                .iter()
+                .map(|image| packed_bit_depth(image, bits))
+                .collect();
+        }
+    }
+
+    let changed = reduced[0].ihdr.color_type != first.color_type
+        || reduced[0].ihdr.bit_depth != first.bit_depth
+        || reduced.iter().zip(images).any(|(r, i)| r.data != i.data);
+    changed.then_some(reduced)
+}
+
+/// Snap near-transparent and near-opaque pixels if an alpha threshold is set, reporting how many
+/// pixels were changed
+fn snap_alpha(png: &mut Arc<PngImage>, opts: &Options, deadline: &Deadline) {
+    if let Some(threshold) = opts.alpha_threshold {
+        if !deadline.passed() {
+            if let Some((reduced, count)) = snapped_alpha_channel(png, threshold) {
+                info!("Snapped alpha values of {count} pixels");
+                *png = Arc::new(reduced);
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use log::debug;
+use rgb::RGBA8;
+use rustc_hash::FxHashMap;
+
+use crate::{
+    colors::{BitDepth, ColorType},
+    headers::IhdrData,
+    png::PngImage,
+};
+
+/// Options for lossy palette quantization (requires the `lossy` feature)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantizeOptions {
+    /// Minimum acceptable quality, from 0 to 100.
+    /// If the quantized image would fall below this, it is discarded.
+    pub min_quality: u8,
+    /// Whether to apply Floyd-Steinberg dithering when remapping the pixels
+    pub dither: bool,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        Self {
+            min_quality: 70,
+            dither: false,
+        }
+    }
+}
+
+/// Perceptual weights of the red, green, blue and alpha channels
+const WEIGHTS: [f32; 4] = [0.5, 1.0, 0.45, 0.625];
+const WEIGHT_SUM: f32 = WEIGHTS[0] + WEIGHTS[1] + WEIGHTS[2] + WEIGHTS[3];
+
+/// A color in weighted, premultiplied space, suitable for distance calculations
+type Vec4 = [f32; 4];
+
+#[inline]
+fn to_vec(c: RGBA8) -> Vec4 {
+    let a = f32::from(c.a) / 255.0;
+    [
+        f32::from(c.r) / 255.0 * a * WEIGHTS[0],
+        f32::from(c.g) / 255.0 * a * WEIGHTS[1],
+        f32::from(c.b) / 255.0 * a * WEIGHTS[2],
+        a * WEIGHTS[3],
+    ]
+}
+
+#[inline]
+fn from_vec(v: Vec4) -> RGBA8 {
+    let a = v[3] / WEIGHTS[3];
+    let a8 = (a * 255.0).round().clamp(0.0, 255.0) as u8;
+    if a8 == 0 {
+        return RGBA8::new(0, 0, 0, 0);
+    }
+    let ch = |i: usize| (v[i] / WEIGHTS[i] / a * 255.0).round().clamp(0.0, 255.0) as u8;
+    RGBA8::new(ch(0), ch(1), ch(2), a8)
+}
+
+#[inline]
+fn distance(a: &Vec4, b: &Vec4) -> f32 {
+    (0..4).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum()
+}
+
+fn nearest(palette: &[Vec4], v: &Vec4) -> (usize, f32) {
+    let mut best = (0, f32::MAX);
+    for (i, p) in palette.iter().enumerate() {
+        let d = distance(p, v);
+        if d < best.1 {
+            best = (i, d);
+        }
+    }
+    best
+}
+
+/// Convert a mean squared error to a quality value from 0 to 100.
+/// This is based on the PSNR, where 20dB or less is 0 and 50dB or more is 100.
+fn mse_to_quality(mse: f64) -> u8 {
+    if mse <= 0.0 {
+        return 100;
+    }
+    let psnr = -10.0 * mse.log10();
+    ((psnr - 20.0) * 100.0 / 30.0).round().clamp(0.0, 100.0) as u8
+}
+
+/// Read the pixels of an RGB or RGBA image as RGBA8, returning None for other color types
+fn rgba_pixels(png: &PngImage) -> Option<Vec<RGBA8>> {
+    let byte_depth = png.bytes_per_channel();
+    let bpp = png.channels_per_pixel() * byte_depth;
+    let sample = |px: &[u8], c: usize| px[c * byte_depth];
+    Some(match png.ihdr.color_type {
+        ColorType::RGB { transparent_color } => png
+            .data
+            .chunks_exact(bpp)
+            .map(|px| {
+                let transparent = transparent_color.is_some_and(|t| {
+                    let val = |c: usize| {
+                        if byte_depth == 2 {
+                            u16::from_be_bytes([px[c * 2], px[c * 2 + 1]])
+                        } else {
+                            u16::from(px[c])
+                        }
+                    };
+                    t.r == val(0) && t.g == val(1) && t.b == val(2)
+                });
+                let a = if transparent { 0 } else { 255 };
+                RGBA8::new(sample(px, 0), sample(px, 1), sample(px, 2), a)
+            })
+            .collect(),
+        ColorType::RGBA => png
+            .data
+            .chunks_exact(bpp)
+            .map(|px| RGBA8::new(sample(px, 0), sample(px, 1), sample(px, 2), sample(px, 3)))
+            .collect(),
+        _ => return None,
+    })
+}
+
+/// A box of histogram entries for median cut
+struct ColorBox {
+    start: usize,
+    end: usize,
+    /// Channel with the greatest variance and the weighted variance sum of that channel
+    split: Option<(usize, f32)>,
+}
+
+impl ColorBox {
+    fn new(entries: &[(Vec4, u32)], start: usize, end: usize) -> Self {
+        let mut split = None;
+        if end - start > 1 {
+            let slice = &entries[start..end];
+            let total: f32 = slice.iter().map(|e| e.1 as f32).sum();
+            for c in 0..4 {
+                let mean = slice.iter().map(|e| e.0[c] * e.1 as f32).sum::<f32>() / total;
+                let var: f32 = slice
+                    .iter()
+                    .map(|e| (e.0[c] - mean) * (e.0[c] - mean) * e.1 as f32)
+                    .sum();
+                if var > 0.0 && split.is_none_or(|(_, v)| var > v) {
+                    split = Some((c, var));
+                }
+            }
+        }
+        Self { start, end, split }
+    }
+}
+
+/// Build a palette of up to 256 colors from the histogram using median cut,
+/// followed by a round of k-means refinement
+fn build_quantized_palette(entries: &mut [(Vec4, u32)]) -> Vec<Vec4> {
+    let mut boxes = vec![ColorBox::new(entries, 0, entries.len())];
+    while boxes.len() < 256 {
+        let Some((idx, _)) = boxes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, b)| b.split.map(|(_, v)| (i, v)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+        else {
+            break;
+        };
+        let b = boxes.swap_remove(idx);
+        let (channel, _) = b.split.unwrap();
+        let slice = &mut entries[b.start..b.end];
+        slice.sort_unstable_by(|x, y| x.0[channel].total_cmp(&y.0[channel]));
+        // Split at the weighted median, ensuring both halves are non-empty
+        let half = slice.iter().map(|e| u64::from(e.1)).sum::<u64>() / 2;
+        let mut acc = 0;
+        let mut mid = 1;
+        for (i, e) in slice.iter().enumerate() {
+            acc += u64::from(e.1);
+            if acc >= half {
+                mid = i + 1;
+                break;
+            }
+        }
+        let mid = b.start + mid.clamp(1, slice.len() - 1);
+        boxes.push(ColorBox::new(entries, b.start, mid));
+        boxes.push(ColorBox::new(entries, mid, b.end));
+    }
+
+    let mut palette: Vec<Vec4> = boxes
+        .iter()
+        .map(|b| {
+            let slice = &entries[b.start..b.end];
+            let total: f32 = slice.iter().map(|e| e.1 as f32).sum();
+            let mut mean = [0.0; 4];
+            for e in slice {
+                for (m, v) in mean.iter_mut().zip(e.0) {
+                    *m += v * e.1 as f32 / total;
+                }
+            }
+            mean
+        })
+        .collect();
+
+    // Refine the palette by moving each color to the mean of the entries nearest to it
+    let mut sums = vec![([0.0_f64; 4], 0_u64); palette.len()];
+    for e in entries.iter() {
+        let (i, _) = nearest(&palette, &e.0);
+        for c in 0..4 {
+            sums[i].0[c] += f64::from(e.0[c]) * f64::from(e.1);
+        }
+        sums[i].1 += u64::from(e.1);
+    }
+    for (p, (sum, count)) in palette.iter_mut().zip(sums) {
+        if count > 0 {
+            for c in 0..4 {
+                p[c] = (sum[c] / count as f64) as f32;
+            }
+        }
+    }
+    palette
+}
+
+/// Attempt to quantize an RGB or RGBA image with more than 256 colors to an indexed palette,
+/// returning the quantized image if it meets the minimum quality
+#[must_use]
+pub fn quantized_to_indexed(png: &PngImage, opts: &QuantizeOptions) -> Option<PngImage> {
+    let pixels = rgba_pixels(png)?;
+
+    let mut histogram: FxHashMap<RGBA8, u32> = FxHashMap::default();
+    for &px in &pixels {
+        *histogram.entry(px).or_default() += 1;
+    }
+    if histogram.len() <= 256 {
+        // This can be handled losslessly
+        return None;
+    }
+
+    let mut entries: Vec<_> = histogram.iter().map(|(&c, &n)| (to_vec(c), n)).collect();
+    let vec_palette = build_quantized_palette(&mut entries);
+    // Fully transparent palette entries should not retain any color
+    let palette: Vec<RGBA8> = vec_palette.iter().copied().map(from_vec).collect();
+    let vec_palette: Vec<Vec4> = palette.iter().copied().map(to_vec).collect();
+
+    let mut data = Vec::with_capacity(pixels.len());
+    let mut error_sum = 0.0_f64;
+    if opts.dither {
+        let width = png.ihdr.width as usize;
+        // Error buffers for the current and next line, with a pixel of padding on each side
+        let mut cur_err = vec![[0.0_f32; 4]; width + 2];
+        let mut next_err = vec![[0.0_f32; 4]; width + 2];
+        let mut prev_pass = None;
+        let mut offset = 0;
+        for line in png.scan_lines(false) {
+            if line.pass != prev_pass {
+                cur_err.fill([0.0; 4]);
+                next_err.fill([0.0; 4]);
+                prev_pass = line.pass;
+            }
+            for (x, px) in pixels[offset..offset + line.num_pixels].iter().enumerate() {
+                let orig = to_vec(*px);
+                let idx = if px.a == 0 {
+                    // Don't spread error through fully transparent areas
+                    nearest(&vec_palette, &orig).0
+                } else {
+                    let mut target = orig;
+                    for c in 0..4 {
+                        // Limit the error to avoid streaks in flat areas
+                        let err = cur_err[x + 1][c].clamp(-0.1, 0.1);
+                        target[c] = (target[c] + err).clamp(0.0, WEIGHTS[c]);
+                    }
+                    let (idx, _) = nearest(&vec_palette, &target);
+                    let chosen = &vec_palette[idx];
+                    for c in 0..4 {
+                        let err = target[c] - chosen[c];
+                        cur_err[x + 2][c] += err * 7.0 / 16.0;
+                        next_err[x][c] += err * 3.0 / 16.0;
+                        next_err[x + 1][c] += err * 5.0 / 16.0;
+                        next_err[x + 2][c] += err / 16.0;
+                    }
+                    idx
+                };
+                error_sum += f64::from(distance(&orig, &vec_palette[idx]));
+                data.push(idx as u8);
+            }
+            std::mem::swap(&mut cur_err, &mut next_err);
+            next_err.fill([0.0; 4]);
+            offset += line.num_pixels;
+        }
+    } else {
+        let mut map: FxHashMap<RGBA8, u8> = FxHashMap::default();
+        for (&color, &count) in &histogram {
+            let (idx, dist) = nearest(&vec_palette, &to_vec(color));
+            error_sum += f64::from(dist) * f64::from(count);
+            map.insert(color, idx as u8);
+        }
+        data.extend(pixels.iter().map(|px| map[px]));
+    }
+
+    let mse = error_sum / pixels.len() as f64 / f64::from(WEIGHT_SUM);
+    let quality = mse_to_quality(mse);
+    if quality < opts.min_quality {
+        debug!(
+            "Quantization quality {quality} is below the minimum of {}",
+            opts.min_quality
+        );
+        return None;
+    }
+    debug!(
+        "Quantized {} colors to {} (quality {quality})",
+        histogram.len(),
+        palette.len()
+    );
+
+    Some(PngImage {
+        data,
+        ihdr: IhdrData {
+            color_type: ColorType::Indexed { palette },
+            bit_depth: BitDepth::Eight,
+            ..png.ihdr
+        },
+    })
+}
@@ -0,0 +1,729 @@
+use indexmap::IndexSet;
+use log::{debug, warn};
+use miniz_oxide::{
+    DataFormat, MZError, MZFlush, MZStatus,
+    deflate::{
+        core::{CompressorOxide, create_comp_flags_from_zip_params},
+        stream::deflate,
+    },
+    inflate::stream::{InflateState, inflate},
+};
+use rayon::prelude::*;
+use rgb::{ComponentMap, RGB16, RGBA8, RGBA16};
+use rustc_hash::FxHashMap;
+use std::sync::Arc;
+
+#[cfg(not(feature = "parallel"))]
+use crate::rayon;
+use crate::{
+    Deadline, Deflater, FilterStrategy, Options, PngError, PngResult,
+    colors::{BitDepth, ColorType},
+    filters::{LineFilter, RowFilter},
+    headers::{
+        IhdrData, file_header_is_valid, parse_ihdr_chunk, parse_next_chunk, postprocess_chunks,
+        preprocess_chunks,
+    },
+    png::{PngChunks, PngData, PngImage, scan_lines::ScanLineRanges},
+    report_format,
+    text::apply_text_edits,
+    verify::first_mismatch,
+};
+
+/// The approximate number of bytes of scan lines to encode at once
+const BAND_SIZE: usize = 1 << 20;
+
+/// Optimize an image a few scan lines at a time, if its decompressed size exceeds the streaming
+/// threshold. Reductions are decided by a first pass over the pixels and applied on the fly in
+/// a second pass, which filters and compresses each line as it is decoded.
+///
+/// Returns `None` if the image should be optimized in full instead, otherwise the output along
+/// with whether it must be kept even if it is not smaller.
+pub fn optimize_streaming(
+    data: &[u8],
+    opts: &Options,
+    deadline: &Deadline,
+) -> PngResult<Option<(Vec<u8>, bool)>> {
+    let Some(threshold) = opts.stream_decompressed_size else {
+        return Ok(None);
+    };
+    // Check the size from the header before reading the rest of the chunks
+    if peek_ihdr(data).is_none_or(|ihdr| ihdr.raw_data_size() <= threshold) {
+        return Ok(None);
+    }
+    let PngChunks {
+        ihdr,
+        mut idat_data,
+        mut aux_chunks,
+        frames,
+        is_cgbi,
+        recovered,
+    } = PngData::read_chunks(data, opts)?;
+    if is_cgbi || !frames.is_empty() || opts.recover.is_some() {
+        debug!("Animated, CgBI and damaged images can't be streamed, processing in full");
+        return Ok(None);
+    }
+    debug!(
+        "    {}x{} pixels, streaming {} bytes of image data",
+        ihdr.width,
+        ihdr.height,
+        ihdr.raw_data_size()
+    );
+    if opts
+        .interlace
+        .is_some_and(|interlace| interlace != ihdr.interlaced)
+    {
+        warn!("Interlacing can't be changed when streaming an image");
+    }
+    if opts.is_lossy() {
+        warn!("Lossy options are not applied when streaming an image");
+    }
+
+    let mut opts = opts.to_owned();
+    apply_text_edits(&mut aux_chunks, &opts)?;
+    let exif_stripped = preprocess_chunks(&mut aux_chunks, &mut opts);
+
+    let stats = PixelStats::gather(&ihdr, &idat_data)?;
+    let new_ihdr = reduced_header(&ihdr, &stats, &opts);
+    if new_ihdr != ihdr {
+        report_format(
+            "Transformed image to ",
+            &PngImage {
+                ihdr: new_ihdr.clone(),
+                data: Vec::new(),
+            },
+        );
+    }
+
+    // Without IDAT recoding, the image data is only encoded again if the format has changed
+    let recode = opts.idat_recoding || new_ihdr != ihdr;
+    let new_idat_data = if recode {
+        let idat_len = idat_data.len();
+        let new_idat_data = encode(&ihdr, &idat_data, &new_ihdr, &opts, deadline)?;
+        debug!(
+            "    IDAT size = {} bytes (from {idat_len})",
+            new_idat_data.len()
+        );
+        new_idat_data
+    } else {
+        debug!("    Keeping the original IDAT data");
+        std::mem::take(&mut idat_data)
+    };
+
+    // Only the header matters here, as the pixels can change only if the format does
+    let original = PngImage {
+        ihdr,
+        data: Vec::new(),
+    };
+    let mut png = PngData {
+        raw: Arc::new(PngImage {
+            ihdr: new_ihdr,
+            data: Vec::new(),
+        }),
+        idat_data: new_idat_data,
+        aux_chunks,
+        frames: Vec::new(),
+        recovered,
+    };
+    postprocess_chunks(&mut png.aux_chunks, &png.raw, &original);
+    let output = png.output();
+
+    if opts.verify && !opts.is_lossy() && recode {
+        verify_streaming(&output, &original.ihdr, &idat_data)?;
+        debug!("Verified output matches the input");
+    }
+    Ok(Some((output, recovered || exif_stripped)))
+}
+
+/// Read the image header, if it is the first chunk
+fn peek_ihdr(data: &[u8]) -> Option<IhdrData> {
+    if !file_header_is_valid(data.get(0..8)?) {
+        return None;
+    }
+    let mut byte_offset = 8;
+    match parse_next_chunk(data, &mut byte_offset, true) {
+        Ok(Some(chunk)) if &chunk.name == b"IHDR" => parse_ihdr_chunk(chunk.data, None, None).ok(),
+        _ => None,
+    }
+}
+
+/// Decodes compressed image data one scan line at a time
+struct LineDecoder<'a> {
+    ihdr: &'a IhdrData,
+    ranges: ScanLineRanges,
+    state: Box<InflateState>,
+    input: &'a [u8],
+    bpp: usize,
+    filtered: Vec<u8>,
+    line: Vec<u8>,
+    pass: Option<u8>,
+    inflated: usize,
+}
+
+impl<'a> LineDecoder<'a> {
+    fn new(ihdr: &'a IhdrData, input: &'a [u8]) -> Self {
+        let bytes_per_channel = if ihdr.bit_depth == BitDepth::Sixteen {
+            2
+        } else {
+            1
+        };
+        Self {
+            ihdr,
+            ranges: ScanLineRanges::from_ihdr(ihdr),
+            state: InflateState::new_boxed(DataFormat::Zlib),
+            input,
+            bpp: bytes_per_channel * ihdr.color_type.channels_per_pixel() as usize,
+            filtered: Vec::new(),
+            line: Vec::new(),
+            pass: None,
+            inflated: 0,
+        }
+    }
+
+    /// Decode the next scan line, returning its pixels and the pass it belongs to
+    fn next_line(&mut self) -> PngResult<Option<(Vec<RGBA16>, Option<u8>)>> {
+        let Some((len, pass, num_pixels)) = self.ranges.next() else {
+            self.finish()?;
+            return Ok(None);
+        };
+        self.filtered.resize(len, 0);
+        let mut filled = 0;
+        while filled < len {
+            let (status, written) = self.inflate(filled)?;
+            filled += written;
+            if filled < len && (status == MZStatus::StreamEnd || written == 0) {
+                return Err(PngError::TruncatedData);
+            }
+        }
+        self.inflated += len;
+
+        if self.pass != pass || self.line.len() != len - 1 {
+            self.line = vec![0; len - 1];
+            self.pass = pass;
+        }
+        let filter = RowFilter::try_from(self.filtered[0]).map_err(|()| PngError::InvalidData)?;
+        let mut unfiltered = Vec::with_capacity(len - 1);
+        filter.unfilter_line(self.bpp, &self.filtered[1..], &self.line, &mut unfiltered);
+        self.line = unfiltered;
+
+        // Convert the line as a single row image
+        let image = PngImage {
+            ihdr: IhdrData {
+                width: num_pixels as u32,
+                height: 1,
+                interlaced: false,
+                ..self.ihdr.clone()
+            },
+            data: self.line.clone(),
+        };
+        Ok(Some((image.rgba16_pixels(), pass)))
+    }
+
+    /// Check that the stream ends after the last scan line, with a valid checksum
+    fn finish(&mut self) -> PngResult<()> {
+        self.filtered.resize(1, 0);
+        loop {
+            let (status, written) = self.inflate(0)?;
+            if written > 0 {
+                return Err(PngError::InflatedDataTooLong(self.inflated));
+            }
+            if status == MZStatus::StreamEnd {
+                return Ok(());
+            }
+            if self.input.is_empty() {
+                return Err(PngError::TruncatedData);
+            }
+        }
+    }
+
+    /// Inflate more data into the filtered line from the given offset, returning the status and
+    /// the number of bytes written
+    fn inflate(&mut self, offset: usize) -> PngResult<(MZStatus, usize)> {
+        let result = inflate(
+            &mut self.state,
+            self.input,
+            &mut self.filtered[offset..],
+            MZFlush::None,
+        );
+        let status = result.status.map_err(|e| match e {
+            MZError::Buf => PngError::TruncatedData,
+            _ => PngError::InvalidData,
+        })?;
+        self.input = &self.input[result.bytes_consumed..];
+        Ok((status, result.bytes_written))
+    }
+}
+
+/// Properties of the pixels of an image, used to decide on reductions before encoding
+#[derive(Debug)]
+struct PixelStats {
+    /// Whether any value can't be represented in 8 bits
+    not_8_bit: bool,
+    /// Whether any pixel is not gray
+    not_gray: bool,
+    /// Whether any pixel is not fully opaque
+    not_opaque: bool,
+    /// The number of bits needed for the gray values, if they are all 8-bit
+    gray_bits: u8,
+    /// The distinct colors, until there are too many for a palette
+    colors: Option<IndexSet<RGBA16>>,
+}
+
+impl PixelStats {
+    fn gather(ihdr: &IhdrData, idat_data: &[u8]) -> PngResult<Self> {
+        let mut stats = Self {
+            not_8_bit: false,
+            not_gray: false,
+            not_opaque: false,
+            gray_bits: 1,
+            colors: Some(IndexSet::new()),
+        };
+        let mut decoder = LineDecoder::new(ihdr, idat_data);
+        while let Some((pixels, _)) = decoder.next_line()? {
+            let mut prev = None;
+            for px in pixels {
+                if prev == Some(px) {
+                    continue;
+                }
+                prev = Some(px);
+                stats.not_8_bit |= px.iter().any(|v| to_depth(v, BitDepth::Eight).is_none());
+                stats.not_gray |= px.r != px.g || px.g != px.b;
+                stats.not_opaque |= px.a != u16::MAX;
+                stats.gray_bits = stats.gray_bits.max(gray_bits(px.r));
+                if let Some(colors) = &mut stats.colors {
+                    colors.insert(px);
+                    if colors.len() > 256 {
+                        stats.colors = None;
+                    }
+                }
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// The number of bits needed for a gray value, assuming it is 8-bit
+fn gray_bits(value: u16) -> u8 {
+    [BitDepth::One, BitDepth::Two, BitDepth::Four]
+        .into_iter()
+        .find(|&depth| to_depth(value, depth).is_some())
+        .map_or(8, |depth| depth as u8)
+}
+
+/// Convert a 16-bit value to the given bit depth, if it can be represented exactly
+fn to_depth(value: u16, depth: BitDepth) -> Option<u16> {
+    let max = (1_u32 << depth as u8) - 1;
+    let scale = u32::from(u16::MAX) / max;
+    (u32::from(value) % scale == 0).then(|| (u32::from(value) / scale) as u16)
+}
+
+/// Convert a value of the given bit depth to 16 bits
+fn from_depth(value: u16, depth: BitDepth) -> u16 {
+    let max = (1_u32 << depth as u8) - 1;
+    (u32::from(value) * (u32::from(u16::MAX) / max)) as u16
+}
+
+/// Determine the smallest format that can represent the image, according to the options
+fn reduced_header(ihdr: &IhdrData, stats: &PixelStats, opts: &Options) -> IhdrData {
+    let depth = ihdr.bit_depth;
+    let bit_depth = if depth == BitDepth::Sixteen && (stats.not_8_bit || !opts.bit_depth_reduction)
+    {
+        BitDepth::Sixteen
+    } else {
+        BitDepth::Eight
+    };
+    // Transparency keyed on a single color is retained, otherwise it needs alpha or a palette
+    let (gray_key, rgb_key) = match ihdr.color_type {
+        ColorType::Grayscale { transparent_shade } => {
+            let key = transparent_shade.map(|v| from_depth(v, depth));
+            (key, key.map(|v| RGB16::new(v, v, v)))
+        }
+        ColorType::RGB { transparent_color } => {
+            let key = transparent_color.map(|c| c.map(|v| from_depth(v, depth)));
+            (key.filter(|c| c.r == c.g && c.g == c.b).map(|c| c.r), key)
+        }
+        _ => (None, None),
+    };
+    let keyed = matches!(
+        ihdr.color_type,
+        ColorType::Grayscale { .. } | ColorType::RGB { .. }
+    );
+    let needs_alpha = stats.not_opaque && !keyed;
+    // Without grayscale reduction, gray images stay gray and other images can't become gray
+    let (allow_gray, allow_palette) = if opts.grayscale_reduction {
+        (true, opts.palette_reduction)
+    } else {
+        (
+            ihdr.color_type.is_gray(),
+            opts.palette_reduction && !ihdr.color_type.is_gray(),
+        )
+    };
+    let palette_bits = |len: usize| match len {
+        0..=2 => BitDepth::One,
+        3..=4 => BitDepth::Two,
+        5..=16 => BitDepth::Four,
+        _ => BitDepth::Eight,
+    };
+
+    let (color_type, bit_depth) = match &ihdr.color_type {
+        ColorType::Indexed { .. } if !opts.color_type_reduction && !opts.palette_reduction => {
+            return ihdr.clone();
+        }
+        ColorType::Indexed { palette } if !opts.color_type_reduction => {
+            // Only drop unused entries from the palette
+            let palette: Vec<_> = palette
+                .iter()
+                .filter(|c| {
+                    stats
+                        .colors
+                        .as_ref()
+                        .is_some_and(|s| s.contains(&rgba16(**c)))
+                })
+                .copied()
+                .collect();
+            let bit_depth = if opts.bit_depth_reduction {
+                palette_bits(palette.len())
+            } else {
+                depth
+            };
+            (ColorType::Indexed { palette }, bit_depth)
+        }
+        color_type if !opts.color_type_reduction => {
+            let bit_depth = if depth == BitDepth::Sixteen {
+                bit_depth
+            } else {
+                depth
+            };
+            (
+                key_to_depth(color_type, gray_key, rgb_key, bit_depth),
+                bit_depth,
+            )
+        }
+        // Grayscale, unless a palette would allow a lower bit depth
+        _ if !stats.not_gray
+            && allow_gray
+            && !needs_alpha
+            && !stats.colors.as_ref().is_some_and(|c| {
+                allow_palette
+                    && bit_depth == BitDepth::Eight
+                    && opts.bit_depth_reduction
+                    && (palette_bits(c.len()) as u8) < stats.gray_bits
+            }) =>
+        {
+            let bit_depth = if bit_depth == BitDepth::Sixteen {
+                bit_depth
+            } else if opts.bit_depth_reduction {
+                BitDepth::try_from(stats.gray_bits).unwrap()
+            } else if ihdr.color_type.is_gray() {
+                depth
+            } else {
+                BitDepth::Eight
+            };
+            let color_type = ColorType::Grayscale {
+                transparent_shade: None,
+            };
+            (
+                key_to_depth(&color_type, gray_key, None, bit_depth),
+                bit_depth,
+            )
+        }
+        // Without palette reduction, an existing palette is kept as it is
+        ColorType::Indexed { palette } if !opts.palette_reduction => {
+            let bit_depth = if opts.bit_depth_reduction {
+                palette_bits(palette.len())
+            } else {
+                depth
+            };
+            (
+                ColorType::Indexed {
+                    palette: palette.clone(),
+                },
+                bit_depth,
+            )
+        }
+        _ if allow_palette && bit_depth == BitDepth::Eight && stats.colors.is_some() => {
+            let mut palette: Vec<_> = stats.colors.iter().flatten().map(|c| rgba8(*c)).collect();
+            // Placing transparent colors first keeps the tRNS chunk short
+            palette.sort_by_key(|c| c.a == 255);
+            let bit_depth = if opts.bit_depth_reduction {
+                palette_bits(palette.len())
+            } else {
+                BitDepth::Eight
+            };
+            (ColorType::Indexed { palette }, bit_depth)
+        }
+        _ if !stats.not_gray && allow_gray => (ColorType::GrayscaleAlpha, bit_depth),
+        _ if needs_alpha => (ColorType::RGBA, bit_depth),
+        _ => {
+            let color_type = ColorType::RGB {
+                transparent_color: None,
+            };
+            (
+                key_to_depth(&color_type, None, rgb_key, bit_depth),
+                bit_depth,
+            )
+        }
+    };
+    IhdrData {
+        color_type,
+        bit_depth,
+        ..ihdr.clone()
+    }
+}
+
+/// Set the transparency key of a color type from the 16-bit key, converted to the bit depth. A
+/// key that can't be represented can't match any pixel, so it is dropped.
+fn key_to_depth(
+    color_type: &ColorType,
+    gray_key: Option<u16>,
+    rgb_key: Option<RGB16>,
+    depth: BitDepth,
+) -> ColorType {
+    match color_type {
+        ColorType::Grayscale { .. } => ColorType::Grayscale {
+            transparent_shade: gray_key.and_then(|v| to_depth(v, depth)),
+        },
+        ColorType::RGB { .. } => ColorType::RGB {
+            transparent_color: rgb_key.and_then(|c| {
+                Some(RGB16::new(
+                    to_depth(c.r, depth)?,
+                    to_depth(c.g, depth)?,
+                    to_depth(c.b, depth)?,
+                ))
+            }),
+        },
+        _ => color_type.clone(),
+    }
+}
+
+fn rgba16(c: RGBA8) -> RGBA16 {
+    c.map(|v| u16::from(v) * 257)
+}
+
+fn rgba8(c: RGBA16) -> RGBA8 {
+    c.map(|v| (v >> 8) as u8)
+}
+
+/// Encode a line of pixels in the format of the image header, which must be able to represent
+/// them exactly
+fn encode_pixels(
+    pixels: &[RGBA16],
+    ihdr: &IhdrData,
+    palette: &FxHashMap<RGBA16, u16>,
+    output: &mut Vec<u8>,
+) {
+    let depth = ihdr.bit_depth;
+    let value = |v: u16| to_depth(v, depth).unwrap_or_default();
+    let values = pixels.iter().flat_map(|px| {
+        let values = match ihdr.color_type {
+            ColorType::Grayscale { .. } => [value(px.r), 0, 0, 0],
+            ColorType::GrayscaleAlpha => [value(px.r), value(px.a), 0, 0],
+            ColorType::RGB { .. } => [value(px.r), value(px.g), value(px.b), 0],
+            ColorType::RGBA => [value(px.r), value(px.g), value(px.b), value(px.a)],
+            ColorType::Indexed { .. } => [palette.get(px).copied().unwrap_or_default(), 0, 0, 0],
+        };
+        values
+            .into_iter()
+            .take(ihdr.color_type.channels_per_pixel() as usize)
+    });
+    match depth {
+        BitDepth::Sixteen => output.extend(values.flat_map(u16::to_be_bytes)),
+        BitDepth::Eight => output.extend(values.map(|v| v as u8)),
+        _ => {
+            let bits = depth as u8;
+            let (mut byte, mut used) = (0_u8, 0);
+            for v in values {
+                byte |= (v as u8) << (8 - bits - used);
+                used += bits;
+                if used == 8 {
+                    output.push(byte);
+                    (byte, used) = (0, 0);
+                }
+            }
+            if used > 0 {
+                output.push(byte);
+            }
+        }
+    }
+}
+
+/// Filters and compresses the lines of an image as they are decoded, using one filter strategy
+struct LineEncoder {
+    filter: LineFilter,
+    compressor: Box<CompressorOxide>,
+    /// The filtered lines that haven't been compressed, plus any needed as context for filtering
+    filtered: Vec<u8>,
+    buffer: Vec<u8>,
+    idat_data: Vec<u8>,
+}
+
+impl LineEncoder {
+    fn new(strategy: FilterStrategy, ihdr: &IhdrData, level: u8, alpha: bool) -> Self {
+        let bytes_per_channel = if ihdr.bit_depth == BitDepth::Sixteen {
+            2
+        } else {
+            1
+        };
+        let bpp = bytes_per_channel * ihdr.color_type.channels_per_pixel() as usize;
+        let alpha_bytes = if alpha && ihdr.color_type.has_alpha() {
+            bytes_per_channel
+        } else {
+            0
+        };
+        // A positive window size selects the zlib format
+        let flags = create_comp_flags_from_zip_params(level.into(), 1, 0);
+        Self {
+            filter: LineFilter::new(strategy, bpp, alpha_bytes),
+            compressor: Box::new(CompressorOxide::new(flags)),
+            filtered: Vec::new(),
+            buffer: vec![0; 1 << 16],
+            idat_data: Vec::new(),
+        }
+    }
+
+    fn push_line(&mut self, line: &[u8], pass: Option<u8>) -> PngResult<()> {
+        let start = self.filtered.len();
+        self.filter.filter_line(line, pass, &mut self.filtered);
+        self.compress(start, MZFlush::None)?;
+        // Discard the lines that are no longer needed as context
+        let keep = self.filter.context_len(line.len());
+        let excess = self.filtered.len().saturating_sub(keep);
+        self.filtered.drain(..excess);
+        Ok(())
+    }
+
+    fn finish(mut self) -> PngResult<Vec<u8>> {
+        self.compress(self.filtered.len(), MZFlush::Finish)?;
+        Ok(self.idat_data)
+    }
+
+    /// Compress the filtered data from the given offset
+    fn compress(&mut self, start: usize, flush: MZFlush) -> PngResult<()> {
+        let mut input = &self.filtered[start..];
+        loop {
+            let result = deflate(&mut self.compressor, input, &mut self.buffer, flush);
+            let status = result
+                .status
+                .map_err(|e| PngError::new(&format!("Failed to compress image data: {e:?}")))?;
+            self.idat_data
+                .extend_from_slice(&self.buffer[..result.bytes_written]);
+            input = &input[result.bytes_consumed..];
+            let done = match flush {
+                MZFlush::Finish => status == MZStatus::StreamEnd,
+                _ => input.is_empty() && result.bytes_written < self.buffer.len(),
+            };
+            if done {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Reduce, filter and compress the image data, trying each filter strategy at once
+fn encode(
+    ihdr: &IhdrData,
+    idat_data: &[u8],
+    new_ihdr: &IhdrData,
+    opts: &Options,
+    deadline: &Deadline,
+) -> PngResult<Vec<u8>> {
+    let mut filters = opts.filters.clone();
+    if filters.is_empty() {
+        filters.insert(if new_ihdr.bit_depth as u8 >= 8 {
+            FilterStrategy::Bigrams
+        } else {
+            FilterStrategy::NONE
+        });
+    }
+    // Zopfli can't compress a stream, so use the strongest standard level instead
+    let level = match opts.deflater {
+        Deflater::Libdeflater { compression } => compression.min(9),
+        #[cfg(feature = "zopfli")]
+        Deflater::Zopfli(_) => 9,
+    };
+    debug!(
+        "Trying {} filters with streaming level {level}",
+        filters.len()
+    );
+    let mut encoders: Vec<_> = filters
+        .into_iter()
+        .map(|f| {
+            let encoder = LineEncoder::new(f.clone(), new_ihdr, level, opts.optimize_alpha);
+            (f, encoder)
+        })
+        .collect();
+
+    let palette: FxHashMap<_, _> = match &new_ihdr.color_type {
+        ColorType::Indexed { palette } => palette
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, c)| (rgba16(*c), i as u16))
+            .collect(),
+        _ => FxHashMap::default(),
+    };
+    let mut decoder = LineDecoder::new(ihdr, idat_data);
+    let mut band = Vec::new();
+    let mut band_size = 0;
+    loop {
+        let next = decoder.next_line()?;
+        let done = next.is_none();
+        if let Some((pixels, pass)) = next {
+            let mut line = Vec::new();
+            encode_pixels(&pixels, new_ihdr, &palette, &mut line);
+            band_size += line.len();
+            band.push((line, pass));
+            if band_size < BAND_SIZE {
+                continue;
+            }
+        }
+        // Each strategy processes the band of lines in parallel
+        encoders.par_iter_mut().try_for_each(|(_, encoder)| {
+            band.iter()
+                .try_for_each(|(line, pass)| encoder.push_line(line, *pass))
+        })?;
+        band.clear();
+        band_size = 0;
+        if done {
+            break;
+        }
+        if encoders.len() > 1 && deadline.passed() {
+            // Continue with only the strategy that is compressing best so far
+            encoders.sort_by_key(|(_, e)| e.idat_data.len());
+            encoders.truncate(1);
+        }
+    }
+
+    let mut best = None;
+    for (filter, encoder) in encoders {
+        let data = encoder.finish()?;
+        debug!("    f = {filter}: {} bytes", data.len());
+        if best.as_ref().is_none_or(|b: &Vec<u8>| data.len() < b.len()) {
+            best = Some(data);
+        }
+    }
+    Ok(best.unwrap())
+}
+
+/// Check that the output decodes to the same pixels as the input, one scan line at a time
+fn verify_streaming(output: &[u8], ihdr: &IhdrData, idat_data: &[u8]) -> PngResult<()> {
+    let failed = |pixel| PngError::VerificationFailed(0, pixel);
+    let new = PngData::read_chunks(output, &Options::default()).map_err(|_| failed(None))?;
+    if (new.ihdr.width, new.ihdr.height) != (ihdr.width, ihdr.height) {
+        return Err(failed(None));
+    }
+    let mut old_decoder = LineDecoder::new(ihdr, idat_data);
+    let mut new_decoder = LineDecoder::new(&new.ihdr, &new.idat_data);
+    let mut offset = 0;
+    loop {
+        match (old_decoder.next_line()?, new_decoder.next_line()) {
+            (Some((a, _)), Ok(Some((b, _)))) => {
+                if let Some(pixel) = first_mismatch(&a, &b) {
+                    return Err(failed(Some(offset + pixel)));
+                }
+                offset += a.len();
+            }
+            (None, Ok(None)) => return Ok(()),
+            _ => return Err(failed(Some(offset))),
+        }
+    }
+}
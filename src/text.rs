@@ -0,0 +1,274 @@
+use log::{debug, warn};
+
+use crate::{
+    Options, PngResult,
+    deflate::{Deflater, inflate},
+    error::PngError,
+    headers::Chunk,
+    xmp::XMP_KEYWORD,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// A textual metadata entry, stored in a tEXt, zTXt or iTXt chunk
+///
+/// The chunk type is chosen automatically: iTXt is used if a language tag or translated keyword
+/// is present or the text cannot be represented in Latin-1, otherwise zTXt is used if the text
+/// is compressed, or tEXt if it is not.
+pub struct TextChunk {
+    /// The keyword identifying the text, e.g. "Copyright". It must be 1-79 printable Latin-1
+    /// characters, with no leading, trailing or consecutive spaces.
+    pub keyword: String,
+    /// The text itself
+    pub text: String,
+    /// The language of the text, e.g. "en-GB" (international text only)
+    pub language_tag: String,
+    /// The keyword translated into the language of the text (international text only)
+    pub translated_keyword: String,
+    /// Whether to compress the text
+    pub compressed: bool,
+}
+
+impl TextChunk {
+    /// Construct a new uncompressed text entry with the given keyword
+    #[must_use]
+    pub fn new(keyword: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            keyword: keyword.into(),
+            text: text.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Whether the keyword is valid according to the PNG specification
+    #[must_use]
+    pub fn is_valid_keyword(keyword: &str) -> bool {
+        (1..=79).contains(&keyword.chars().count())
+            && keyword
+                .chars()
+                .all(|c| matches!(c, ' '..='~' | '\u{A1}'..='\u{FF}'))
+            && !keyword.starts_with(' ')
+            && !keyword.ends_with(' ')
+            && !keyword.contains("  ")
+    }
+
+    /// Check that the entry can be stored in a PNG text chunk
+    pub fn validate(&self) -> PngResult<()> {
+        if !Self::is_valid_keyword(&self.keyword) {
+            return Err(PngError::InvalidKeyword(self.keyword.clone()));
+        }
+        if [&self.text, &self.translated_keyword]
+            .iter()
+            .any(|s| s.contains('\0'))
+        {
+            return Err(PngError::new("Text must not contain null characters"));
+        }
+        if !self
+            .language_tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(PngError::new(&format!(
+                "Invalid language tag: {:?}",
+                self.language_tag
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether the entry must be stored in an iTXt chunk
+    fn is_international(&self) -> bool {
+        !self.language_tag.is_empty()
+            || !self.translated_keyword.is_empty()
+            || latin1(&self.text).is_none()
+    }
+
+    /// Construct the chunk for this entry, compressing the text with the given deflater if needed
+    pub(crate) fn to_chunk(&self, deflater: Deflater) -> PngResult<Chunk> {
+        self.validate()?;
+        self.encode(self.is_international(), self.compressed, deflater)
+    }
+
+    /// Encode the entry as either an iTXt chunk or a tEXt/zTXt chunk
+    ///
+    /// The keyword, and the text if not international, must be representable in Latin-1.
+    pub(crate) fn encode(
+        &self,
+        international: bool,
+        compressed: bool,
+        deflater: Deflater,
+    ) -> PngResult<Chunk> {
+        let mut data = latin1(&self.keyword).unwrap();
+        data.push(0);
+        let name = if international {
+            data.extend([u8::from(compressed), 0]);
+            data.extend(self.language_tag.as_bytes());
+            data.push(0);
+            data.extend(self.translated_keyword.as_bytes());
+            data.push(0);
+            if compressed {
+                data.extend(deflater.deflate(self.text.as_bytes(), None)?);
+            } else {
+                data.extend(self.text.as_bytes());
+            }
+            *b"iTXt"
+        } else if compressed {
+            data.push(0); // Compression method
+            data.extend(deflater.deflate(&latin1(&self.text).unwrap(), None)?);
+            *b"zTXt"
+        } else {
+            data.extend(latin1(&self.text).unwrap());
+            *b"tEXt"
+        };
+        Ok(Chunk { name, data })
+    }
+
+    /// Parse a tEXt, zTXt or iTXt chunk, returning None if it is not a valid text chunk
+    pub(crate) fn from_chunk(chunk: &Chunk, max_size: Option<usize>) -> Option<Self> {
+        let (keyword, rest) = split_null(&chunk.data)?;
+        let keyword = from_latin1(keyword);
+        match &chunk.name {
+            b"tEXt" => Some(Self::new(keyword, from_latin1(rest))),
+            b"zTXt" => {
+                let (&0, compressed) = rest.split_first()? else {
+                    return None; // Unknown compression method
+                };
+                Some(Self {
+                    compressed: true,
+                    ..Self::new(keyword, from_latin1(&inflate_text(compressed, max_size)?))
+                })
+            }
+            b"iTXt" => {
+                let (&[flag, method], rest) = rest.split_first_chunk()?;
+                let (language_tag, rest) = split_null(rest)?;
+                let (translated_keyword, text) = split_null(rest)?;
+                let text = match (flag, method) {
+                    (0, _) => text.to_vec(),
+                    (1, 0) => inflate_text(text, max_size)?,
+                    _ => return None,
+                };
+                Some(Self {
+                    keyword,
+                    text: String::from_utf8(text).ok()?,
+                    language_tag: String::from_utf8(language_tag.to_vec()).ok()?,
+                    translated_keyword: String::from_utf8(translated_keyword.to_vec()).ok()?,
+                    compressed: flag == 1,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Encode a string as Latin-1, if possible
+fn latin1(s: &str) -> Option<Vec<u8>> {
+    s.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+/// Decode a Latin-1 string
+fn from_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+/// Split a byte string at the first null separator
+fn split_null(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let pos = bytes.iter().position(|&b| b == 0)?;
+    Some((&bytes[..pos], &bytes[pos + 1..]))
+}
+
+/// Decompress the text of a chunk
+fn inflate_text(data: &[u8], max_size: Option<usize>) -> Option<Vec<u8>> {
+    // The decompressed size is unknown and text can be highly compressible (e.g. XMP padding),
+    // so start with a modest buffer and grow it as needed, up to a default limit of 1MB
+    let limit = max_size.unwrap_or(1_000_000);
+    let mut out_size = (data.len() * 10).max(1000).min(limit);
+    loop {
+        match inflate(data, out_size) {
+            Ok(text) => return Some(text),
+            Err(PngError::InflatedDataTooLong(_)) if out_size < limit => {
+                out_size = (out_size * 4).min(limit);
+            }
+            Err(e) => {
+                warn!("Failed to decompress text: {e}");
+                return None;
+            }
+        }
+    }
+}
+
+/// Get the keyword of a text chunk, or None if it is not a text chunk
+fn chunk_keyword(chunk: &Chunk) -> Option<&[u8]> {
+    if !matches!(&chunk.name, b"tEXt" | b"zTXt" | b"iTXt") {
+        return None;
+    }
+    chunk.data.split(|&b| b == 0).next()
+}
+
+/// Add and remove text chunks as specified in the options
+///
+/// Any existing text chunks with the same keyword as a new one are replaced.
+pub(crate) fn apply_text_edits(aux_chunks: &mut Vec<Chunk>, opts: &Options) -> PngResult<()> {
+    if opts.set_text.is_empty() && opts.remove_text.is_empty() {
+        return Ok(());
+    }
+    let mut keywords = Vec::with_capacity(opts.set_text.len() + opts.remove_text.len());
+    for keyword in &opts.remove_text {
+        if !TextChunk::is_valid_keyword(keyword) {
+            return Err(PngError::InvalidKeyword(keyword.clone()));
+        }
+        keywords.push(latin1(keyword).unwrap());
+    }
+    let new_chunks = opts
+        .set_text
+        .iter()
+        .map(|text| text.to_chunk(opts.deflater))
+        .collect::<PngResult<Vec<_>>>()?;
+    keywords.extend(opts.set_text.iter().map(|t| latin1(&t.keyword).unwrap()));
+
+    let len = aux_chunks.len();
+    aux_chunks.retain(|c| chunk_keyword(c).is_none_or(|k| !keywords.iter().any(|kw| kw == k)));
+    if aux_chunks.len() < len {
+        debug!("Removed {} text chunks", len - aux_chunks.len());
+    }
+    // Text chunks may be placed anywhere, but we keep them before the image data
+    let pos = aux_chunks
+        .iter()
+        .position(|c| &c.name == b"IDAT")
+        .unwrap_or(aux_chunks.len());
+    aux_chunks.splice(pos..pos, new_chunks);
+    Ok(())
+}
+
+/// Recompress compressed text chunks, and compress uncompressed ones, whenever the result is
+/// smaller. The chunk type stays within the same family: tEXt and zTXt may be swapped, while
+/// iTXt only changes its compression flag.
+pub(crate) fn recompress_text_chunks(
+    aux_chunks: &mut [Chunk],
+    deflater: Deflater,
+    max_size: Option<usize>,
+) {
+    for chunk in aux_chunks.iter_mut() {
+        let Some(text) = TextChunk::from_chunk(chunk, max_size) else {
+            continue;
+        };
+        let international = &chunk.name == b"iTXt";
+        let mut candidates = vec![false];
+        // Some readers only support uncompressed XMP, so we don't compress it if it wasn't already
+        if text.compressed || !(international && text.keyword == XMP_KEYWORD) {
+            candidates.push(true);
+        }
+        let best = candidates
+            .into_iter()
+            .filter_map(|compressed| text.encode(international, compressed, deflater).ok())
+            .min_by_key(|c| c.data.len());
+        if let Some(best) = best.filter(|c| c.data.len() < chunk.data.len()) {
+            debug!(
+                "Recompressed {} chunk as {}: {} ({} bytes decrease)",
+                std::str::from_utf8(&chunk.name).unwrap(),
+                std::str::from_utf8(&best.name).unwrap(),
+                best.data.len(),
+                chunk.data.len() - best.data.len()
+            );
+            *chunk = best;
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use log::debug;
+use rgb::RGBA;
+
+use crate::{Options, PngError, PngResult, apng::composited_apng, png::PngData};
+
+/// Check that the output decodes to the same image as the original, comparing the main image and
+/// each composited animation frame. Colors of fully transparent pixels are not compared, as these
+/// may be changed by alpha optimization and reductions.
+///
+/// If `merged_frames` is set, frames of the original that display the same as the one before may
+/// have been merged into it, and so are not required in the output.
+pub fn verify_output(output: &[u8], original: &PngData, merged_frames: bool) -> PngResult<()> {
+    let failed = |frame, pixel| PngError::VerificationFailed(frame, pixel);
+    let new = PngData::from_slice(output, &Options::default()).map_err(|_| failed(0, None))?;
+    if new.raw.ihdr.width != original.raw.ihdr.width
+        || new.raw.ihdr.height != original.raw.ihdr.height
+    {
+        return Err(failed(0, None));
+    }
+    if let Some(pixel) = first_mismatch(&original.raw.rgba16_pixels(), &new.raw.rgba16_pixels()) {
+        return Err(failed(0, Some(pixel)));
+    }
+    if original.frames.is_empty() {
+        return Ok(());
+    }
+
+    // Frames may have been optimized to different regions, so compare the full canvases
+    let Ok(old_canvases) = composited_apng(original) else {
+        debug!("Unable to composite frames, comparing them individually");
+        if new.frames.len() != original.frames.len() {
+            return Err(failed(1, None));
+        }
+        for (i, (a, b)) in original.frames.iter().zip(&new.frames).enumerate() {
+            let frame = i as u32 + 1;
+            let (Ok(a), Ok(b)) = (a.image(&original.raw.ihdr), b.image(&new.raw.ihdr)) else {
+                return Err(failed(frame, None));
+            };
+            if (a.ihdr.width, a.ihdr.height) != (b.ihdr.width, b.ihdr.height) {
+                return Err(failed(frame, None));
+            }
+            if let Some(pixel) = first_mismatch(&a.rgba16_pixels(), &b.rgba16_pixels()) {
+                return Err(failed(frame, Some(pixel)));
+            }
+        }
+        return Ok(());
+    };
+    let new_canvases = composited_apng(&new).map_err(|_| failed(1, None))?;
+    if !merged_frames {
+        if new_canvases.len() != old_canvases.len() {
+            return Err(failed(
+                new_canvases.len().min(old_canvases.len()) as u32 + 1,
+                None,
+            ));
+        }
+        for (i, (a, b)) in old_canvases.iter().zip(&new_canvases).enumerate() {
+            if let Some(pixel) = first_mismatch(a, b) {
+                return Err(failed(i as u32 + 1, Some(pixel)));
+            }
+        }
+        return Ok(());
+    }
+
+    // Walk the original timeline, where each frame must either be shown by the next frame of the
+    // output or be identical to the one shown before it, having been merged into it
+    let mut new_canvases = new_canvases.iter();
+    let mut shown: Option<&Vec<RGBA<u8>>> = None;
+    for (i, a) in old_canvases.iter().enumerate() {
+        let frame = i as u32 + 1;
+        if shown.is_some_and(|b| first_mismatch(a, b).is_none()) {
+            continue;
+        }
+        let b = new_canvases.next().ok_or(failed(frame, None))?;
+        if let Some(pixel) = first_mismatch(a, b) {
+            return Err(failed(frame, Some(pixel)));
+        }
+        shown = Some(b);
+    }
+    if new_canvases.next().is_some() {
+        return Err(failed(old_canvases.len() as u32 + 1, None));
+    }
+    Ok(())
+}
+
+/// Find the index of the first pixel that differs, treating all fully transparent pixels as equal
+pub(crate) fn first_mismatch<T: PartialEq + Default>(
+    a: &[RGBA<T>],
+    b: &[RGBA<T>],
+) -> Option<usize> {
+    if a.len() != b.len() {
+        return Some(a.len().min(b.len()));
+    }
+    a.iter()
+        .zip(b)
+        .position(|(a, b)| a != b && !(a.a == T::default() && b.a == T::default()))
+}
@@ -0,0 +1,216 @@
+use log::{debug, warn};
+
+use crate::{deflate::Deflater, headers::Chunk, text::TextChunk};
+
+/// The keyword of the iTXt chunk containing the XMP packet
+pub(crate) const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Properties to remove from the XMP packet
+///
+/// Each entry is either a qualified property name such as `xmpMM:History`, or a namespace prefix
+/// such as `photoshop` which matches all properties in that namespace. The conventional prefixes
+/// are expected to be used.
+pub struct XmpStrip {
+    /// Properties or namespaces to remove
+    pub strip: Vec<String>,
+    /// Properties or namespaces to retain, even if they match `strip`
+    pub keep: Vec<String>,
+}
+
+impl Default for XmpStrip {
+    /// Remove editing history, document ancestors and thumbnails, while keeping rights and
+    /// licensing information
+    fn default() -> Self {
+        Self {
+            strip: [
+                "xmpMM:History",
+                "xmpMM:DerivedFrom",
+                "xmpMM:Ingredients",
+                "xmpMM:Pantry",
+                "xmpMM:Manifest",
+                "photoshop:DocumentAncestors",
+                "xmp:Thumbnails",
+                "xap:Thumbnails",
+                "xmpGImg",
+            ]
+            .map(String::from)
+            .to_vec(),
+            keep: ["dc:rights", "xmpRights", "cc", "plus"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+impl XmpStrip {
+    /// Whether a property with the given qualified name should be removed
+    fn removes(&self, name: &str) -> bool {
+        let Some((prefix, _)) = name.split_once(':') else {
+            return false;
+        };
+        // Never remove the structure of the packet
+        if matches!(prefix, "rdf" | "x" | "xml" | "xmlns") {
+            return false;
+        }
+        let matches = |list: &[String]| list.iter().any(|s| s == name || s == prefix);
+        matches(&self.strip) && !matches(&self.keep)
+    }
+}
+
+/// A piece of XML markup
+enum Token<'a> {
+    /// A start tag, along with its name and whether it is self-closing
+    Start(&'a str, &'a str, bool),
+    /// An end tag
+    End(&'a str),
+    /// Text content
+    Text(&'a str),
+    /// Any other markup, such as comments and processing instructions
+    Other(&'a str),
+}
+
+/// Split XML into tokens, returning None if it is malformed
+fn tokenize(xml: &str) -> Option<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let mut rest = xml;
+    while !rest.is_empty() {
+        if !rest.starts_with('<') {
+            let end = rest.find('<').unwrap_or(rest.len());
+            tokens.push(Token::Text(&rest[..end]));
+            rest = &rest[end..];
+            continue;
+        }
+        let terminator = ["<!--", "<![CDATA[", "<?"]
+            .into_iter()
+            .zip(["-->", "]]>", "?>"])
+            .find(|(start, _)| rest.starts_with(start));
+        let (token, len) = if let Some((_, end)) = terminator {
+            let len = rest.find(end)? + end.len();
+            (Token::Other(&rest[..len]), len)
+        } else {
+            // Find the end of the tag, skipping over quoted attribute values
+            let mut quote = None;
+            let len = rest.char_indices().find_map(|(i, c)| {
+                match (quote, c) {
+                    (None, '"' | '\'') => quote = Some(c),
+                    (Some(q), _) if q == c => quote = None,
+                    (None, '>') => return Some(i + 1),
+                    _ => {}
+                }
+                None
+            })?;
+            let tag = &rest[..len];
+            let token = if tag.starts_with("</") {
+                Token::End(tag[2..len - 1].trim())
+            } else if tag.starts_with("<!") {
+                Token::Other(tag)
+            } else {
+                let name_end = tag[1..]
+                    .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+                    .map_or(tag.len(), |i| i + 1);
+                Token::Start(tag, &tag[1..name_end], tag.ends_with("/>"))
+            };
+            (token, len)
+        };
+        tokens.push(token);
+        rest = &rest[len..];
+    }
+    Some(tokens)
+}
+
+/// Remove the matching attributes from a start tag, returning None if it is malformed
+fn filtered_tag(tag: &str, name: &str, strip: &XmpStrip) -> Option<String> {
+    let mut output = format!("<{name}");
+    let mut rest = tag[1 + name.len()..].trim_start();
+    loop {
+        if rest.starts_with('>') || rest.starts_with("/>") {
+            output.push_str(rest);
+            return Some(output);
+        }
+        let (attr, value) = rest.split_once('=')?;
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let len = value[1..].find(quote)? + 2;
+        let attr = attr.trim();
+        if !strip.removes(attr) {
+            output.push(' ');
+            output.push_str(attr);
+            output.push('=');
+            output.push_str(&value[..len]);
+        }
+        rest = value[len..].trim_start();
+    }
+}
+
+/// Remove the selected properties and any padding whitespace from an XMP packet, returning None
+/// if it is malformed
+fn sanitized_xmp(xml: &str, strip: &XmpStrip) -> Option<String> {
+    let tokens = tokenize(xml)?;
+    let mut output = String::with_capacity(xml.len());
+    // The depth of nesting within a removed element
+    let mut skip_depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match *token {
+            _ if skip_depth > 0 => match token {
+                Token::Start(_, _, false) => skip_depth += 1,
+                Token::End(_) => skip_depth -= 1,
+                _ => {}
+            },
+            Token::Start(_, name, closed) if strip.removes(name) => {
+                if !closed {
+                    skip_depth = 1;
+                }
+            }
+            Token::Start(tag, name, _) => output.push_str(&filtered_tag(tag, name, strip)?),
+            Token::Text(text) if text.trim().is_empty() => {
+                // Whitespace between elements is insignificant, but keep it as element content
+                let is_content = i > 0
+                    && matches!(tokens[i - 1], Token::Start(_, _, false))
+                    && matches!(tokens.get(i + 1), Some(Token::End(_)));
+                if is_content {
+                    output.push_str(text);
+                }
+            }
+            Token::End(text) => {
+                output.push_str("</");
+                output.push_str(text);
+                output.push('>');
+            }
+            Token::Text(text) | Token::Other(text) => output.push_str(text),
+        }
+    }
+    (skip_depth == 0).then_some(output)
+}
+
+/// Sanitize the XMP packet in any iTXt chunk, leaving it unchanged if it cannot be parsed
+pub(crate) fn sanitize_xmp(
+    aux_chunks: &mut [Chunk],
+    strip: &XmpStrip,
+    deflater: Deflater,
+    max_size: Option<usize>,
+) {
+    for chunk in aux_chunks.iter_mut().filter(|c| &c.name == b"iTXt") {
+        let Some(mut text) = TextChunk::from_chunk(chunk, max_size) else {
+            continue;
+        };
+        if text.keyword != XMP_KEYWORD {
+            continue;
+        }
+        let Some(xmp) = sanitized_xmp(&text.text, strip) else {
+            warn!("Failed to parse XMP packet, leaving it unchanged");
+            continue;
+        };
+        if xmp.len() < text.text.len() {
+            debug!(
+                "Sanitized XMP packet: {} ({} bytes decrease)",
+                xmp.len(),
+                text.text.len() - xmp.len()
+            );
+            text.text = xmp;
+            if let Ok(new) = text.encode(true, text.compressed, deflater) {
+                *chunk = new;
+            }
+        }
+    }
+}
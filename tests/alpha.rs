@@ -6,6 +6,7 @@ use std::{
 use oxipng::{internal_tests::*, *};
 
 const GRAYSCALE_ALPHA: u8 = 4;
+const RGB: u8 = 2;
 const RGBA: u8 = 6;
 
 fn get_opts(input: &Path) -> (OutFile, oxipng::Options) {
@@ -344,3 +345,55 @@ fn alpha_filter_5_for_grayscale_alpha_8() {
         BitDepth::Eight,
     );
 }
+
+#[test]
+fn alpha_threshold_snaps_values() {
+    let data = vec![
+        10, 20, 30, 2, //
+        10, 20, 30, 4, //
+        10, 20, 30, 128, //
+        10, 20, 30, 253,
+    ];
+    let raw = RawImage::new(4, 1, ColorType::RGBA, BitDepth::Eight, data).unwrap();
+    let opts = oxipng::Options {
+        alpha_threshold: Some(3),
+        color_type_reduction: false,
+        ..Default::default()
+    };
+    let output = raw.create_optimized_png(&opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    assert_eq!(
+        png.raw.data,
+        vec![
+            10, 20, 30, 0, 10, 20, 30, 4, 10, 20, 30, 128, 10, 20, 30, 255
+        ]
+    );
+}
+
+#[test]
+fn alpha_threshold_allows_reduction() {
+    let mut data = Vec::new();
+    for i in 0..4096_u32 {
+        let (x, y) = ((i % 64) as u8, (i / 64) as u8);
+        let a = if (x / 8 + y / 8) % 2 == 0 { 1 } else { 254 };
+        data.extend_from_slice(&[x * 4, y * 4, x ^ y, a]);
+    }
+    let raw = RawImage::new(64, 64, ColorType::RGBA, BitDepth::Eight, data).unwrap();
+
+    let opts = oxipng::Options {
+        optimize_alpha: true,
+        palette_reduction: false,
+        ..Default::default()
+    };
+    let output = raw.create_optimized_png(&opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    assert_eq!(png.raw.ihdr.color_type.png_header_code(), RGBA);
+
+    let opts = oxipng::Options {
+        alpha_threshold: Some(1),
+        ..opts
+    };
+    let output = raw.create_optimized_png(&opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    assert_eq!(png.raw.ihdr.color_type.png_header_code(), RGB);
+}
@@ -10,6 +10,8 @@ use std::{
 const GRAY: u8 = 0;
 const RGB: u8 = 2;
 const INDEXED: u8 = 3;
+const GRAY_ALPHA: u8 = 4;
+const RGBA: u8 = 6;
 
 fn get_opts(input: &Path) -> (OutFile, Options) {
     let options = Options {
@@ -307,6 +309,644 @@ fn strip_chunks_none() {
     );
 }
 
+#[test]
+fn hdr_chunks() {
+    let input = PathBuf::from("tests/files/hdr_pq.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.strip = StripChunks::Safe;
+    opts.scale_16 = true;
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Sixteen,
+        RGB,
+        BitDepth::Sixteen,
+        |png| {
+            assert_eq!(count_chunk(png, b"cICP"), 1);
+            assert_eq!(count_chunk(png, b"mDCV"), 1);
+            assert_eq!(count_chunk(png, b"cLLI"), 1);
+        },
+        |png| {
+            assert_eq!(count_chunk(png, b"cICP"), 1);
+            assert_eq!(count_chunk(png, b"mDCV"), 1);
+            assert_eq!(count_chunk(png, b"cLLI"), 1);
+        },
+    );
+}
+
+#[test]
+fn hdr_chunks_invalid() {
+    let input = PathBuf::from("tests/files/hdr_invalid.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.scale_16 = true;
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Sixteen,
+        GRAY,
+        BitDepth::Eight,
+        |_| {},
+        |png| {
+            assert_eq!(count_chunk(png, b"cICP"), 0);
+            assert_eq!(count_chunk(png, b"mDCV"), 0);
+            assert_eq!(count_chunk(png, b"cLLI"), 1);
+        },
+    );
+}
+
+#[test]
+fn unsafe_to_copy_chunks() {
+    let input = PathBuf::from("tests/files/unsafe_to_copy.png");
+    let (output, opts) = get_opts(&input);
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        GRAY,
+        BitDepth::Eight,
+        |png| {
+            assert_eq!(count_chunk(png, b"prVT"), 1);
+            assert_eq!(count_chunk(png, b"prVt"), 1);
+        },
+        |png| {
+            assert_eq!(count_chunk(png, b"prVT"), 0);
+            assert_eq!(count_chunk(png, b"prVt"), 1);
+        },
+    );
+}
+
+#[test]
+fn unsafe_to_copy_chunks_strict() {
+    let input = PathBuf::from("tests/files/unsafe_to_copy.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.strict_chunks = true;
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+        |_| {},
+        |png| {
+            assert_eq!(count_chunk(png, b"prVT"), 1);
+            assert_eq!(count_chunk(png, b"prVt"), 1);
+        },
+    );
+}
+
+#[test]
+fn gama_chrm_to_srgb() {
+    let input = PathBuf::from("tests/files/gama_chrm_srgb.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.strip = StripChunks::Strip(indexset![*b"tEXt"]);
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+        |png| {
+            assert_eq!(count_chunk(png, b"gAMA"), 1);
+            assert_eq!(count_chunk(png, b"cHRM"), 1);
+            assert_eq!(count_chunk(png, b"sRGB"), 0);
+        },
+        |png| {
+            assert_eq!(count_chunk(png, b"gAMA"), 0);
+            assert_eq!(count_chunk(png, b"cHRM"), 0);
+            assert_eq!(count_chunk(png, b"sRGB"), 1);
+        },
+    );
+}
+
+#[test]
+fn gama_chrm_overridden() {
+    let input = PathBuf::from("tests/files/gama_chrm_overridden.png");
+    let (output, mut opts) = get_opts(&input);
+    // An overridden chunk is still kept if requested
+    opts.strip = StripChunks::Keep(indexset![*b"sRGB", *b"cHRM"]);
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+        |png| {
+            assert_eq!(count_chunk(png, b"gAMA"), 1);
+            assert_eq!(count_chunk(png, b"cHRM"), 1);
+        },
+        |png| {
+            assert_eq!(count_chunk(png, b"gAMA"), 0);
+            assert_eq!(count_chunk(png, b"cHRM"), 1);
+            let srgb = png.aux_chunks.iter().find(|c| &c.name == b"sRGB");
+            assert_eq!(srgb.unwrap().data, [1]);
+        },
+    );
+}
+
+#[test]
+fn gama_chrm_disallow_gray() {
+    let input = PathBuf::from("tests/files/gama_chrm_adobe.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.strip = StripChunks::Strip(indexset![*b"tEXt"]);
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+        |_| {},
+        |png| {
+            assert_eq!(count_chunk(png, b"gAMA"), 1);
+            assert_eq!(count_chunk(png, b"cHRM"), 1);
+        },
+    );
+}
+
+#[test]
+fn gama_chrm_srgb_allow_gray() {
+    // Chunks are not stripped by default, but an sRGB-equivalent cHRM still allows grayscale
+    let input = PathBuf::from("tests/files/gama_chrm_srgb_gray.png");
+    let (output, opts) = get_opts(&input);
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        GRAY,
+        BitDepth::Eight,
+        |_| {},
+        |png| {
+            assert_eq!(count_chunk(png, b"gAMA"), 1);
+            assert_eq!(count_chunk(png, b"cHRM"), 1);
+        },
+    );
+}
+
+#[test]
+fn icc_to_cicp_replace() {
+    let input = PathBuf::from("tests/files/icc_display_p3.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.icc_to_cicp = Some(CicpMode::Replace);
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+        |png| {
+            assert_eq!(count_chunk(png, b"iCCP"), 1);
+            assert_eq!(count_chunk(png, b"cICP"), 0);
+        },
+        |png| {
+            assert_eq!(count_chunk(png, b"iCCP"), 0);
+            let cicp = png.aux_chunks.iter().find(|c| &c.name == b"cICP");
+            assert_eq!(cicp.unwrap().data, [12, 13, 0, 1]);
+        },
+    );
+}
+
+#[test]
+fn icc_to_cicp_srgb() {
+    // A profile that could be replaced with an sRGB chunk is left alone, even if it can't be
+    // replaced because chunks are not being stripped
+    let input = PathBuf::from("tests/files/profile_srgb_no_strip_disallow_gray.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.icc_to_cicp = Some(CicpMode::Replace);
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        INDEXED,
+        BitDepth::Eight,
+        |_| {},
+        |png| {
+            assert_eq!(count_chunk(png, b"iCCP"), 1);
+            assert_eq!(count_chunk(png, b"cICP"), 0);
+        },
+    );
+}
+
+#[test]
+fn icc_to_cicp_add() {
+    let input = PathBuf::from("tests/files/icc_pq.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.icc_to_cicp = Some(CicpMode::Add);
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+        |_| {},
+        |png| {
+            assert_eq!(count_chunk(png, b"iCCP"), 1);
+            let cicp = png.aux_chunks.iter().position(|c| &c.name == b"cICP");
+            let iccp = png.aux_chunks.iter().position(|c| &c.name == b"iCCP");
+            assert!(cicp < iccp);
+            assert_eq!(png.aux_chunks[cicp.unwrap()].data, [9, 16, 0, 1]);
+        },
+    );
+}
+
+#[test]
+fn slim_icc() {
+    let input = PathBuf::from("tests/files/icc_bloated.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.slim_icc = true;
+    let icc = |png: &PngData| {
+        let iccp = &png
+            .aux_chunks
+            .iter()
+            .find(|c| &c.name == b"iCCP")
+            .unwrap()
+            .data;
+        let name_len = iccp.iter().position(|&b| b == 0).unwrap();
+        inflate(&iccp[name_len + 2..], 100_000).unwrap()
+    };
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+        |png| assert_eq!(icc(png).len(), 3104),
+        |png| {
+            let icc = icc(png);
+            assert!(icc.len() < 600);
+            assert_eq!(icc[0..4], (icc.len() as u32).to_be_bytes());
+            assert_eq!(icc[84..100], [0; 16]);
+            assert!(icc.windows(4).any(|w| w == b"para"));
+            assert!(!icc.windows(4).any(|w| w == b"deDE"));
+        },
+    );
+}
+
+#[test]
+fn set_text() {
+    let input = PathBuf::from("tests/files/strip_chunks_list.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.set_text = vec![
+        TextChunk::new("Software", "oxipng"),
+        TextChunk {
+            compressed: true,
+            ..TextChunk::new("Copyright", "ACME ".repeat(20))
+        },
+        TextChunk {
+            language_tag: "de".to_owned(),
+            translated_keyword: "Titel".to_owned(),
+            ..TextChunk::new("Title", "Grüße")
+        },
+    ];
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+        |png| {
+            assert_eq!(count_chunk(png, b"tEXt"), 3);
+        },
+        |png| {
+            assert_eq!(count_chunk(png, b"tEXt"), 3);
+            assert_eq!(count_chunk(png, b"zTXt"), 1);
+            assert_eq!(count_chunk(png, b"iTXt"), 2);
+            let text = png.aux_chunks.iter().rfind(|c| &c.name == b"tEXt").unwrap();
+            assert_eq!(text.data, b"Software\0oxipng");
+            let itxt = png.aux_chunks.iter().rfind(|c| &c.name == b"iTXt").unwrap();
+            assert_eq!(itxt.data, "Title\0\0\0de\0Titel\0Grüße".as_bytes());
+        },
+    );
+}
+
+#[test]
+fn remove_text() {
+    let input = PathBuf::from("tests/files/strip_chunks_list.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.set_text = vec![TextChunk::new("Software", "oxipng")];
+    opts.remove_text = vec!["date:create".to_owned(), "XML:com.adobe.xmp".to_owned()];
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+        |png| {
+            assert_eq!(count_chunk(png, b"tEXt"), 3);
+            assert_eq!(count_chunk(png, b"iTXt"), 1);
+        },
+        |png| {
+            let keywords: Vec<_> = png
+                .aux_chunks
+                .iter()
+                .filter(|c| &c.name == b"tEXt" || &c.name == b"iTXt")
+                .map(|c| c.data.split(|&b| b == 0).next().unwrap())
+                .collect();
+            assert_eq!(keywords, [b"date:modify".as_slice(), b"Software"]);
+        },
+    );
+
+    // Removed text is written out even if the output is no smaller
+    let input = std::fs::read("tests/files/text_after_plte.png").unwrap();
+    let opts = Options {
+        remove_text: vec!["Comment".to_owned()],
+        stream_decompressed_size: Some(0),
+        ..Default::default()
+    };
+    let output = oxipng::optimize_from_memory(&input, &opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    assert_eq!(count_chunk(&png, b"tEXt"), 0);
+}
+
+#[test]
+fn strip_exif() {
+    let input = std::fs::read("tests/files/exif.png").unwrap();
+    let opts = Options {
+        strip_exif: vec![
+            ExifStrip::Gps,
+            ExifStrip::MakerNote,
+            ExifStrip::SerialNumbers,
+            ExifStrip::Thumbnail,
+        ],
+        ..Default::default()
+    };
+    let output = oxipng::optimize_from_memory(&input, &opts).unwrap();
+
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    let exif = &png
+        .aux_chunks
+        .iter()
+        .find(|c| &c.name == b"eXIf")
+        .unwrap()
+        .data;
+    let contains = |s: &[u8]| exif.windows(s.len()).any(|w| w == s);
+    assert!(contains(b"ACME Corp"));
+    assert!(!contains(b"MAKERNOTE"));
+    assert!(!contains(b"SN12345678"));
+    assert!(!contains(b"THUMBDAT"));
+    assert!(!contains(&2607_u32.to_le_bytes()));
+    // IFD0 keeps the orientation, copyright and EXIF IFD pointer, but no longer links to IFD1
+    assert_eq!(exif[8..10], [3, 0]);
+    assert_eq!(exif[10..12], 0x0112_u16.to_le_bytes());
+    assert_eq!(exif[46..50], [0; 4]);
+
+    // A specific tag can be removed instead
+    let opts = Options {
+        strip_exif: vec![ExifStrip::Tag(0x8298)],
+        ..Default::default()
+    };
+    let output = oxipng::optimize_from_memory(&input, &opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    let exif = &png
+        .aux_chunks
+        .iter()
+        .find(|c| &c.name == b"eXIf")
+        .unwrap()
+        .data;
+    assert!(!exif.windows(9).any(|w| w == b"ACME Corp"));
+    assert!(exif.windows(9).any(|w| w == b"MAKERNOTE"));
+
+    // When nothing is stripped, an output that is no smaller is not kept
+    let input = std::fs::read("tests/files/text_after_plte.png").unwrap();
+    let output = oxipng::optimize_from_memory(&input, &opts).unwrap();
+    assert_eq!(output, input);
+}
+
+#[test]
+fn strip_xmp() {
+    let input = std::fs::read("tests/files/xmp.png").unwrap();
+    let xmp = |opts: &Options| {
+        let output = oxipng::optimize_from_memory(&input, opts).unwrap();
+        let png = PngData::from_slice(&output, opts).unwrap();
+        let itxt = png.aux_chunks.iter().find(|c| &c.name == b"iTXt").unwrap();
+        let header = b"XML:com.adobe.xmp\0\0\0\0\0";
+        assert_eq!(itxt.data[..header.len()], *header);
+        String::from_utf8(itxt.data[header.len()..].to_vec()).unwrap()
+    };
+
+    let opts = Options {
+        strip_xmp: Some(XmpStrip::default()),
+        ..Default::default()
+    };
+    let sanitized = xmp(&opts);
+    assert!(sanitized.len() < 1500);
+    assert!(sanitized.starts_with("<?xpacket begin="));
+    assert!(sanitized.ends_with("</x:xmpmeta><?xpacket end=\"w\"?>"));
+    assert!(sanitized.contains(r#"<rdf:li xml:lang="x-default">Copyright ACME Corp</rdf:li>"#));
+    assert!(sanitized.contains(r#"xmpMM:DocumentID="xmp.did:0123456789""#));
+    assert!(sanitized.contains("<photoshop:ColorMode>3</photoshop:ColorMode>"));
+    for removed in [
+        "xmp:Thumbnails",
+        "THUMBNAIL",
+        "DocumentAncestors",
+        "xmpMM:History",
+    ] {
+        assert!(!sanitized.contains(removed), "{removed}");
+    }
+
+    // Whole namespaces can be removed, except for those that are kept
+    let opts = Options {
+        strip_xmp: Some(XmpStrip {
+            strip: vec!["xmpMM".to_owned(), "dc".to_owned(), "xmpRights".to_owned()],
+            keep: vec!["dc:rights".to_owned(), "xmpRights".to_owned()],
+        }),
+        ..Default::default()
+    };
+    let sanitized = xmp(&opts);
+    assert!(sanitized.contains("Copyright ACME Corp"));
+    assert!(sanitized.contains(r#"xmpRights:Marked="True""#));
+    assert!(sanitized.contains("xmp:Thumbnails"));
+    for removed in ["xmpMM:DocumentID", "xmpMM:History", "dc:format"] {
+        assert!(!sanitized.contains(removed), "{removed}");
+    }
+}
+
+#[test]
+fn cgbi() {
+    let input = PathBuf::from("tests/files/cgbi.png");
+    let (output, opts) = get_opts(&input);
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGBA,
+        BitDepth::Eight,
+        RGBA,
+        BitDepth::Eight,
+        |_| {},
+        |png| {
+            assert_eq!(count_chunk(png, b"CgBI"), 0);
+            let pixels = png.raw.rgba_pixels().unwrap();
+            assert_eq!(pixels[2 * 16 + 3], RGBA8::new(48, 32, 200, 255));
+            // Colors of partially transparent pixels are recovered approximately
+            let px = pixels[10 * 16 + 5];
+            assert_eq!(px.a, 128);
+            assert!(px.r.abs_diff(80) <= 1 && px.g.abs_diff(160) <= 1 && px.b.abs_diff(200) <= 1);
+        },
+    );
+}
+
+#[test]
+fn strict_cgbi() {
+    let input = PathBuf::from("tests/files/cgbi.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.strict_cgbi = true;
+
+    let result = oxipng::optimize(&InFile::Path(input), &output, &opts);
+    assert!(matches!(result, Err(PngError::CgBILossy(128))));
+}
+
+#[test]
+fn recover_truncated() {
+    let data = std::fs::read("tests/files/recover_truncated.png").unwrap();
+    let reference = PngData::new(
+        Path::new("tests/files/recover_trailing.png"),
+        &Options::default(),
+    )
+    .unwrap()
+    .raw;
+    assert!(matches!(
+        PngData::from_slice(&data, &Options::default()),
+        Err(PngError::TruncatedData)
+    ));
+
+    let opts = Options {
+        recover: Some(RecoveryMode::Pad),
+        ..Default::default()
+    };
+    let output = optimize_from_memory(&data, &opts).unwrap();
+    let png = PngData::from_slice(&output, &Options::default()).unwrap();
+    assert_eq!(png.raw.ihdr.height, 32);
+    // The recovered scan lines are intact and the rest are zeroed
+    let row_bytes = 16 * 3;
+    let recovered = (0..32)
+        .take_while(|&y| {
+            png.raw.data[y * row_bytes..][..row_bytes]
+                == reference.data[y * row_bytes..][..row_bytes]
+        })
+        .count();
+    assert!(recovered > 10 && recovered < 32);
+    assert!(
+        png.raw.data[recovered * row_bytes..]
+            .iter()
+            .all(|&b| b == 0)
+    );
+
+    let opts = Options {
+        recover: Some(RecoveryMode::Crop),
+        ..Default::default()
+    };
+    let output = optimize_from_memory(&data, &opts).unwrap();
+    let png = PngData::from_slice(&output, &Options::default()).unwrap();
+    assert_eq!(png.raw.ihdr.height as usize, recovered);
+    assert_eq!(png.raw.data, reference.data[..recovered * row_bytes]);
+}
+
+#[test]
+fn recover_bad_checksum() {
+    let data = std::fs::read("tests/files/recover_adler.png").unwrap();
+    let reference = PngData::new(
+        Path::new("tests/files/recover_trailing.png"),
+        &Options::default(),
+    )
+    .unwrap()
+    .raw;
+    assert!(matches!(
+        PngData::from_slice(&data, &Options::default()),
+        Err(PngError::InvalidData)
+    ));
+
+    let opts = Options {
+        recover: Some(RecoveryMode::Pad),
+        ..Default::default()
+    };
+    let png = PngData::from_slice(&data, &opts).unwrap();
+    assert!(png.recovered);
+    assert_eq!(png.raw.data, reference.data);
+}
+
+#[test]
+fn recover_trailing_data() {
+    let data = std::fs::read("tests/files/recover_trailing.png").unwrap();
+    assert!(
+        !PngData::from_slice(&data, &Options::default())
+            .unwrap()
+            .recovered
+    );
+
+    let opts = Options {
+        recover: Some(RecoveryMode::Pad),
+        ..Default::default()
+    };
+    assert!(PngData::from_slice(&data, &opts).unwrap().recovered);
+    // The output is written without the trailing data even though it is no smaller
+    let output = optimize_from_memory(&data, &opts).unwrap();
+    assert_ne!(output, data);
+    assert!(output.ends_with(b"IEND\xae\x42\x60\x82"));
+}
+
+#[test]
+fn invalid_text_keyword() {
+    for keyword in [
+        "",
+        " Title",
+        "Title ",
+        "Two  spaces",
+        "Tab\t",
+        &"a".repeat(80),
+    ] {
+        assert!(!TextChunk::is_valid_keyword(keyword));
+    }
+    assert!(TextChunk::is_valid_keyword("Création date"));
+
+    let input = PathBuf::from("tests/files/strip_chunks_list.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.remove_text = vec!["Bad keyword ".to_owned()];
+    let result = oxipng::optimize(&InFile::Path(input), &output, &opts);
+    assert!(matches!(result, Err(PngError::InvalidKeyword(_))));
+}
+
 #[test]
 fn interlacing_0_to_1() {
     let input = PathBuf::from("tests/files/interlacing_0_to_1.png");
@@ -695,3 +1335,222 @@ fn json_dry_run() {
     assert_eq!(result2["input"], path2);
     assert_eq!(result2["status"], "error");
 }
+
+#[test]
+fn chunk_order() {
+    let data = std::fs::read("tests/files/chunk_order.png").unwrap();
+    let png = PngData::from_slice(&data, &Options::default()).unwrap();
+    assert!(png.recovered);
+    // The first of the duplicate chunks is kept
+    let ColorType::Indexed { palette } = &png.raw.ihdr.color_type else {
+        panic!("Expected indexed image");
+    };
+    assert_eq!(palette[0].a, 0x80);
+    assert_eq!(count_chunk(&png, b"sRGB"), 1);
+
+    let opts = Options {
+        palette_reduction: false,
+        bit_depth_reduction: false,
+        ..Default::default()
+    };
+    let output = optimize_from_memory(&data, &opts).unwrap();
+    let mut names = Vec::new();
+    let mut offset = 8;
+    while offset < output.len() {
+        let length = u32::from_be_bytes(output[offset..offset + 4].try_into().unwrap()) as usize;
+        names.push(std::str::from_utf8(&output[offset + 4..offset + 8]).unwrap());
+        offset += length + 12;
+    }
+    assert_eq!(
+        names,
+        [
+            "IHDR", "sRGB", "gAMA", "pHYs", "PLTE", "tRNS", "bKGD", "IDAT", "tEXt", "IEND"
+        ]
+    );
+}
+
+#[test]
+fn verify() {
+    for file in [
+        "rgba_16_should_be_palette_8",
+        "interlaced_rgb_16_should_be_rgb_8",
+        "apng_file",
+    ] {
+        let input = PathBuf::from(format!("tests/files/{file}.png"));
+        let (output, mut opts) = get_opts(&input);
+        opts.verify = true;
+        opts.optimize_alpha = true;
+        opts.interlace = Some(false);
+
+        oxipng::optimize(&InFile::Path(input), &output, &opts).unwrap();
+        remove_file(output.path().unwrap()).ok();
+    }
+}
+
+#[test]
+fn verify_mismatch() {
+    let png = PngData::new(
+        Path::new("tests/files/rgb_8_should_be_rgb_8.png"),
+        &Options::default(),
+    )
+    .unwrap();
+    assert!(verify_output(&png.output(), &png, false).is_ok());
+
+    let mut changed = png.clone();
+    let mut image = PngImage::clone(&png.raw);
+    image.data[100] ^= 1;
+    let (filtered, _) = image.filter_image(FilterStrategy::NONE, false);
+    changed.idat_data = deflate(&filtered, 1, None).unwrap();
+    changed.raw = image.into();
+    assert!(matches!(
+        verify_output(&changed.output(), &png, false),
+        Err(PngError::VerificationFailed(0, Some(33)))
+    ));
+}
+
+#[test]
+fn memory_map() {
+    let input = PathBuf::from("tests/files/rgba_16_should_be_palette_8.png");
+    let (_, mut opts) = get_opts(&input);
+    opts.memory_map = true;
+    let expected =
+        oxipng::optimize_from_memory(&PngData::read_file(&input).unwrap(), &opts).unwrap();
+
+    // Optimize a copy in place, so the output overwrites the mapped file
+    let in_place = input.with_extension("mmap.png");
+    std::fs::copy(&input, &in_place).unwrap();
+    let output = OutFile::Path {
+        path: None,
+        preserve_attrs: false,
+    };
+    oxipng::optimize(&InFile::Path(in_place.clone()), &output, &opts).unwrap();
+    assert_eq!(PngData::read_file(&in_place).unwrap(), expected);
+
+    // Optimizing again without forcing copies the original straight from the mapping
+    opts.force = false;
+    let copy = OutFile::from_path(input.with_extension("mmap.out.png"));
+    oxipng::optimize(&InFile::Path(in_place.clone()), &copy, &opts).unwrap();
+    let copied = PngData::read_file(copy.path().unwrap()).unwrap();
+    remove_file(copy.path().unwrap()).ok();
+    remove_file(in_place).ok();
+    assert_eq!(copied, expected);
+}
+
+#[test]
+fn stream() {
+    let opts = Options {
+        stream_decompressed_size: Some(0),
+        verify: true,
+        force: true,
+        deflater: Deflater::Libdeflater { compression: 4 },
+        ..Options::default()
+    };
+    for entry in std::fs::read_dir("tests/files").unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        if !name.contains("_should_be_") || name.starts_with("apng") {
+            continue;
+        }
+        let input = PngData::read_file(&path).unwrap();
+        let output = oxipng::optimize_from_memory(&input, &opts).unwrap();
+        let original = PngData::from_slice(&input, &Options::default()).unwrap();
+        verify_output(&output, &original, false).unwrap();
+    }
+
+    for (file, color_type, bit_depth) in [
+        ("rgba_16_should_be_palette_8", INDEXED, BitDepth::Eight),
+        ("rgb_16_should_be_grayscale_8", GRAY, BitDepth::Eight),
+        ("grayscale_8_should_be_grayscale_4", GRAY, BitDepth::Four),
+        ("grayscale_8_should_be_palette_1", INDEXED, BitDepth::One),
+        (
+            "interlaced_rgba_16_should_be_rgb_16",
+            RGB,
+            BitDepth::Sixteen,
+        ),
+        (
+            "rgba_8_should_be_grayscale_alpha_8",
+            GRAY_ALPHA,
+            BitDepth::Eight,
+        ),
+    ] {
+        let input = PathBuf::from(format!("tests/files/{file}.png"));
+        let output = oxipng::optimize_from_memory(&PngData::read_file(&input).unwrap(), &opts);
+        let png = PngData::from_slice(&output.unwrap(), &opts).unwrap();
+        assert_eq!(
+            png.raw.ihdr.color_type.png_header_code(),
+            color_type,
+            "{file}"
+        );
+        assert_eq!(png.raw.ihdr.bit_depth, bit_depth, "{file}");
+    }
+
+    // The color profile prevents grayscale reduction, so it must be kept as a palette
+    let input = PathBuf::from("tests/files/profile_srgb_no_strip_disallow_gray.png");
+    let output = oxipng::optimize_from_memory(&PngData::read_file(&input).unwrap(), &opts);
+    let png = PngData::from_slice(&output.unwrap(), &opts).unwrap();
+    assert_eq!(png.raw.ihdr.color_type.png_header_code(), INDEXED);
+    assert!(png.aux_chunks.iter().any(|c| &c.name == b"iCCP"));
+}
+
+#[test]
+fn stream_options() {
+    let opts = Options {
+        stream_decompressed_size: Some(0),
+        palette_reduction: false,
+        ..Options::default()
+    };
+    let input = PathBuf::from("tests/files/rgba_16_should_be_palette_8.png");
+    let output = oxipng::optimize_from_memory(&PngData::read_file(&input).unwrap(), &opts);
+    let png = PngData::from_slice(&output.unwrap(), &opts).unwrap();
+    assert_ne!(png.raw.ihdr.color_type.png_header_code(), INDEXED);
+
+    // Without IDAT recoding, the image data is kept if the format doesn't change
+    let opts = Options {
+        stream_decompressed_size: Some(0),
+        idat_recoding: false,
+        force: true,
+        ..Options::default()
+    };
+    let input = PngData::read_file(Path::new("tests/files/rgb_8_should_be_rgb_8.png")).unwrap();
+    let output = oxipng::optimize_from_memory(&input, &opts).unwrap();
+    let original = PngData::from_slice(&input, &opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    assert_eq!(png.idat_data, original.idat_data);
+}
+
+#[test]
+fn stream_zlib_end() {
+    let opts = Options {
+        stream_decompressed_size: Some(0),
+        ..Options::default()
+    };
+    let mut png = PngData::new(
+        Path::new("tests/files/rgb_8_should_be_rgb_8.png"),
+        &Options::default(),
+    )
+    .unwrap();
+    let (filtered, _) = png.raw.filter_image(FilterStrategy::NONE, false);
+
+    // Data beyond the last scan line
+    png.idat_data = deflate(&[&filtered[..], &[0; 10]].concat(), 1, None).unwrap();
+    let result = oxipng::optimize_from_memory(&png.output(), &opts);
+    assert!(matches!(result, Err(PngError::InflatedDataTooLong(_))));
+
+    // Corrupted checksum
+    png.idat_data = deflate(&filtered, 1, None).unwrap();
+    *png.idat_data.last_mut().unwrap() ^= 1;
+    let result = oxipng::optimize_from_memory(&png.output(), &opts);
+    assert!(matches!(result, Err(PngError::InvalidData)));
+}
+
+#[test]
+fn stream_brute() {
+    let input = PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.stream_decompressed_size = Some(0);
+    opts.verify = true;
+    opts.filters = indexset! {FilterStrategy::Brute { num_lines: 4, level: 1 }};
+
+    oxipng::optimize(&InFile::Path(input), &output, &opts).unwrap();
+    remove_file(output.path().unwrap()).ok();
+}
@@ -75,6 +75,132 @@ fn optimize_apng() {
     assert!(result.is_ok());
 }
 
+#[test]
+fn optimize_apng_reductions() {
+    let input = fs::read("tests/files/apng_rgba_should_be_palette_2.png").unwrap();
+    let opts = Options::default();
+    let output = oxipng::optimize_from_memory(&input, &opts).unwrap();
+
+    let png = internal_tests::PngData::from_slice(&output, &opts).unwrap();
+    assert_eq!(png.raw.ihdr.color_type.png_header_code(), 3);
+    assert_eq!(png.raw.ihdr.bit_depth, BitDepth::Two);
+    assert_eq!(png.frames.len(), 2);
+    for frame in &png.frames {
+        assert!(frame.image(&png.raw.ihdr).is_ok());
+    }
+
+    #[cfg(feature = "sanity-checks")]
+    assert!(internal_tests::validate_output(&output, &input));
+}
+
+#[test]
+fn optimize_apng_frames() {
+    let input = fs::read("tests/files/apng_full_frames.png").unwrap();
+    let opts = Options {
+        optimize_frames: true,
+        ..Options::default()
+    };
+    let output = oxipng::optimize_from_memory(&input, &opts).unwrap();
+
+    let png = internal_tests::PngData::from_slice(&output, &opts).unwrap();
+    assert_eq!(png.frames.len(), 5);
+    for frame in &png.frames {
+        assert!(frame.width < 40 && frame.height < 40);
+        assert!(frame.image(&png.raw.ihdr).is_ok());
+    }
+
+    #[cfg(feature = "sanity-checks")]
+    assert!(internal_tests::validate_output(&output, &input));
+}
+
+#[test]
+fn merge_apng_frames() {
+    let input = fs::read("tests/files/apng_duplicate_frames.png").unwrap();
+    let opts = Options {
+        merge_frames: true,
+        ..Options::default()
+    };
+    let output = oxipng::optimize_from_memory(&input, &opts).unwrap();
+
+    let png = internal_tests::PngData::from_slice(&output, &opts).unwrap();
+    let actl = png.aux_chunks.iter().find(|c| &c.name == b"acTL").unwrap();
+    assert_eq!(actl.data[0..4], 3_u32.to_be_bytes());
+    let fctl = png.aux_chunks.iter().find(|c| &c.name == b"fcTL").unwrap();
+    assert_eq!(fctl.data[20..24], [0, 3, 0, 20]);
+    assert_eq!(png.frames.len(), 2);
+    assert_eq!(
+        (png.frames[0].delay_num, png.frames[0].delay_den),
+        (21, 100)
+    );
+    assert_eq!((png.frames[1].delay_num, png.frames[1].delay_den), (1, 10));
+
+    // Merged frames are only accepted by verification if merging was enabled
+    let original = internal_tests::PngData::from_slice(&input, &opts).unwrap();
+    assert!(internal_tests::verify_output(&output, &original, true).is_ok());
+    assert!(matches!(
+        internal_tests::verify_output(&output, &original, false),
+        Err(PngError::VerificationFailed(_, None))
+    ));
+}
+
+#[test]
+fn validate_apng_frame_bounds() {
+    let input = fs::read("tests/files/apng_frame_out_of_bounds.png").unwrap();
+    let result = oxipng::optimize_from_memory(&input, &Options::default());
+    assert!(matches!(result, Err(PngError::APNGFrameOutOfBounds(2))));
+
+    let opts = Options {
+        fix_errors: true,
+        ..Options::default()
+    };
+    let output = oxipng::optimize_from_memory(&input, &opts).unwrap();
+    let png = internal_tests::PngData::from_slice(&output, &Options::default()).unwrap();
+    let actl = png.aux_chunks.iter().find(|c| &c.name == b"acTL").unwrap();
+    assert_eq!(actl.data[0..4], 5_u32.to_be_bytes());
+    assert_eq!(png.frames.len(), 4);
+}
+
+#[test]
+fn validate_apng_sequence() {
+    let input = fs::read("tests/files/apng_sequence_mismatch.png").unwrap();
+    let result = oxipng::optimize_from_memory(&input, &Options::default());
+    assert!(matches!(result, Err(PngError::APNGSequenceMismatch(3, 4))));
+
+    let opts = Options {
+        fix_errors: true,
+        ..Options::default()
+    };
+    let output = oxipng::optimize_from_memory(&input, &opts).unwrap();
+    let png = internal_tests::PngData::from_slice(&output, &Options::default()).unwrap();
+    assert_eq!(png.frames.len(), 5);
+}
+
+#[test]
+fn export_apng_frames() {
+    let input = fs::read("tests/files/apng_full_frames.png").unwrap();
+    let opts = Options::default();
+    let frames = oxipng::export_frames_from_memory(&input, None, &opts).unwrap();
+    assert_eq!(frames.len(), 6);
+
+    let single = oxipng::export_frames_from_memory(&input, Some(2), &opts).unwrap();
+    assert_eq!(single, vec![frames[2].clone()]);
+
+    let png = internal_tests::PngData::from_slice(&frames[5], &opts).unwrap();
+    assert_eq!(png.raw.ihdr.width, 40);
+    assert!(png.frames.is_empty());
+    assert!(!png.aux_chunks.iter().any(|c| &c.name == b"acTL"));
+
+    let result = oxipng::export_frames_from_memory(&input, Some(6), &opts);
+    assert!(result.is_err());
+}
+
+#[test]
+fn export_apng_frames_blended() {
+    let input = fs::read("tests/files/apng_file.png").unwrap();
+    let frames = oxipng::export_frames_from_memory(&input, None, &Options::from_preset(0));
+    assert_eq!(frames.unwrap().len(), 20);
+}
+
 #[test]
 fn optimize_srgb_icc() {
     let file = fs::read("tests/files/badsrgb.png").unwrap();
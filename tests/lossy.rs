@@ -0,0 +1,64 @@
+#![cfg(feature = "lossy")]
+
+use indexmap::indexset;
+use oxipng::{internal_tests::*, *};
+use std::path::PathBuf;
+
+const INDEXED: u8 = 3;
+
+fn get_opts(min_quality: u8, dither: bool) -> Options {
+    Options {
+        force: true,
+        filters: indexset! {FilterStrategy::NONE},
+        quantize: Some(QuantizeOptions {
+            min_quality,
+            dither,
+        }),
+        ..Default::default()
+    }
+}
+
+fn optimize_file(input: &str, opts: &Options) -> PngData {
+    let input = PathBuf::from(input);
+    let original = PngData::read_file(&input).unwrap();
+    let output = optimize_from_memory(&original, opts).unwrap();
+    PngData::from_slice(&output, opts).unwrap()
+}
+
+#[test]
+fn quantize_rgb() {
+    let png = optimize_file(
+        "tests/files/rgb_8_should_be_rgb_8.png",
+        &get_opts(70, false),
+    );
+    assert_eq!(png.raw.ihdr.color_type.png_header_code(), INDEXED);
+}
+
+#[test]
+fn quantize_rgba_dither() {
+    let png = optimize_file(
+        "tests/files/rgba_8_should_be_rgba_8.png",
+        &get_opts(70, true),
+    );
+    assert_eq!(png.raw.ihdr.color_type.png_header_code(), INDEXED);
+}
+
+#[test]
+fn quantize_below_min_quality() {
+    let png = optimize_file(
+        "tests/files/rgb_8_should_be_rgb_8.png",
+        &get_opts(100, false),
+    );
+    assert_ne!(png.raw.ihdr.color_type.png_header_code(), INDEXED);
+}
+
+#[test]
+fn quantize_not_needed() {
+    // Images that can be indexed losslessly should not be quantized
+    let opts = get_opts(0, false);
+    let input = PathBuf::from("tests/files/rgb_8_should_be_palette_8.png");
+    let original = PngData::from_slice(&PngData::read_file(&input).unwrap(), &opts).unwrap();
+    assert!(
+        quantize::quantized_to_indexed(&original.raw, opts.quantize.as_ref().unwrap()).is_none()
+    );
+}
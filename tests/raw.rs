@@ -94,3 +94,112 @@ fn incorrect_length() {
     )
     .expect_err("Expected incorrect data length");
 }
+
+#[test]
+fn text_chunks() {
+    let mut opts = get_opts();
+    opts.set_text = vec![TextChunk::new("Software", "oxipng")];
+
+    let mut raw = RawImage::new(2, 2, ColorType::RGBA, BitDepth::Eight, vec![0; 16]).unwrap();
+    raw.add_text_chunk(&TextChunk {
+        compressed: true,
+        ..TextChunk::new("Comment", "A test image. ".repeat(10))
+    })
+    .unwrap();
+    raw.add_text_chunk(&TextChunk::new("Bad\0keyword", ""))
+        .expect_err("Expected invalid keyword");
+
+    let output = raw.create_optimized_png(&opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    let names: Vec<_> = png.aux_chunks.iter().map(|c| &c.name).collect();
+    assert_eq!(names, [b"zTXt", b"tEXt", b"IDAT"]);
+}
+
+#[test]
+fn recompress_text_chunks() {
+    let opts = get_opts();
+    let text = "oxipng ".repeat(100);
+
+    let mut raw = RawImage::new(2, 2, ColorType::RGBA, BitDepth::Eight, vec![0; 16]).unwrap();
+    raw.add_png_chunk(*b"tEXt", [b"Comment\0", text.as_bytes()].concat());
+    raw.add_png_chunk(*b"tEXt", b"Short\0text".to_vec());
+    raw.add_png_chunk(*b"iTXt", [b"Title\0\0\0en\0\0", text.as_bytes()].concat());
+    raw.add_png_chunk(
+        *b"iTXt",
+        [b"XML:com.adobe.xmp\0\0\0\0\0", text.as_bytes()].concat(),
+    );
+
+    let output = raw.create_optimized_png(&opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    let names: Vec<_> = png.aux_chunks.iter().map(|c| &c.name).collect();
+    assert_eq!(names, [b"zTXt", b"tEXt", b"iTXt", b"iTXt", b"IDAT"]);
+    assert!(png.aux_chunks[0].data.len() < text.len());
+    // The compression flag of the iTXt chunk is set, except for XMP
+    assert_eq!(png.aux_chunks[2].data[6], 1);
+    assert_eq!(png.aux_chunks[3].data[18], 0);
+}
+
+#[test]
+fn strip_exif_malformed() {
+    let mut opts = get_opts();
+    opts.strip_exif = vec![ExifStrip::Gps];
+
+    let mut raw = RawImage::new(2, 2, ColorType::RGBA, BitDepth::Eight, vec![0; 16]).unwrap();
+    raw.add_png_chunk(*b"eXIf", b"MM\0*\0\0\0\x08\xFF\xFF".to_vec());
+
+    let output = raw.create_optimized_png(&opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    assert!(!png.aux_chunks.iter().any(|c| &c.name == b"eXIf"));
+}
+
+#[test]
+fn unsafe_to_copy_registered_chunks() {
+    let opts = get_opts();
+
+    // pCAL maps raw sample values and dSIG signs the image, so neither survives a reduction
+    let mut raw = RawImage::new(2, 2, ColorType::RGBA, BitDepth::Eight, vec![0; 16]).unwrap();
+    raw.add_png_chunk(*b"pCAL", b"Scale\0\0\0\0\0\0\0\0\xFF\0\x01m\0\x31".to_vec());
+    raw.add_png_chunk(*b"dSIG", b"signature".to_vec());
+
+    let output = raw.create_optimized_png(&opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    assert!(
+        !png.aux_chunks
+            .iter()
+            .any(|c| &c.name == b"pCAL" || &c.name == b"dSIG")
+    );
+}
+
+#[test]
+fn transparent_shade_16_to_8() {
+    let opts = get_opts();
+
+    // The transparent shade 0x00FF must not become 0xFF when the bit depth is reduced
+    let color_type = ColorType::Grayscale {
+        transparent_shade: Some(0x00FF),
+    };
+    let raw = RawImage::new(2, 2, color_type, BitDepth::Sixteen, vec![0xFF; 8]).unwrap();
+
+    let output = raw.create_optimized_png(&opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    assert!(png.raw.rgba16_pixels().iter().all(|px| px.a == u16::MAX));
+}
+
+#[test]
+fn transparent_shade_collision_16_to_8() {
+    let mut opts = get_opts();
+    opts.scale_16 = true;
+
+    // 0x0101 scales to the same value as the transparent shade 0x0100, so scaling must be skipped
+    let color_type = ColorType::Grayscale {
+        transparent_shade: Some(0x0100),
+    };
+    let data = vec![0x01, 0x00, 0x01, 0x01, 0xFF, 0xFF, 0x00, 0x00];
+    let raw = RawImage::new(2, 2, color_type, BitDepth::Sixteen, data).unwrap();
+
+    let output = raw.create_optimized_png(&opts).unwrap();
+    let png = PngData::from_slice(&output, &opts).unwrap();
+    let pixels = png.raw.rgba16_pixels();
+    assert_eq!(pixels[0].a, 0);
+    assert_eq!(pixels[1].a, u16::MAX);
+}